@@ -1,27 +1,184 @@
 export type SoundImage = {
 	url?: string;
 	file?: string;
+	cached?: string;
 };
 
 export type Sound = {
+	id: string;
 	name: string;
 	path: string;
 	volume: number;
 	image?: SoundImage;
+	outputDevices?: DeviceId[];
+	effects?: SoundEffect[];
+	tts?: Speech;
+	folder?: string;
+	hash?: string;
+	tags?: string[];
+	favourite?: boolean;
+	cooldownMs?: number;
 };
 
-export type OutputDevice = {
+export type PlaySource = "click" | "hotkey" | "group" | "api" | "schedule";
+
+export type SoundStats = {
+	count: number;
+	lastPlayed?: number;
+};
+
+export type SortOrder = "relevance" | "name" | "recentlyAdded" | "mostPlayed";
+
+export type SearchQuery = {
+	query?: string;
+	sort?: SortOrder;
+	favouritesOnly?: boolean;
+	page?: number;
+	pageSize?: number;
+};
+
+export type SearchResults = {
+	sounds: Sound[];
+	total: number;
+	page: number;
+	pageCount: number;
+};
+
+export type Speech = {
+	text: string;
+	voice: string;
+	rate: number;
+	pitch: number;
+};
+
+export type SoundEffect =
+	| { type: "speed"; factor: number }
+	| { type: "pitch"; semitones: number }
+	| { type: "equalizer"; lowGainDb: number; midGainDb: number; highGainDb: number }
+	| { type: "echo"; delayMs: number; feedback: number; mix: number }
+	| { type: "reverb"; roomSize: number; mix: number };
+
+export type SoundGroup = {
+	name: string;
+	sounds: string[];
+	avoidRepeats: boolean;
+};
+
+export type DeviceId = {
+	host: string;
 	name: string;
+	index: number;
+};
+
+export type OutputDevice = DeviceId & {
 	volume: number;
 };
 
+export type LimiterConfig = {
+	enabled: boolean;
+	thresholdDb: number;
+};
+
+export type PluginConfig = {
+	path: string;
+	format: "Vst2";
+	bypass: boolean;
+	parameters: number[];
+	chunk: string | null;
+};
+
+export type PluginChainConfig = {
+	device: DeviceId;
+	plugins: PluginConfig[];
+};
+
+export type PluginsConfig = {
+	folders: string[];
+};
+
+export type PluginInfo = {
+	path: string;
+	format: "Vst2";
+	name: string;
+	vendor: string;
+	parameters: number;
+};
+
+export type PluginParameter = {
+	index: number;
+	name: string;
+	value: number;
+	text: string;
+	label: string;
+};
+
+export type FolderBoard = {
+	name: string;
+	path: string;
+	ignored: string[];
+};
+
 export type SoundsConfig = {
 	copyFile: boolean;
 };
 
+export type RecordingConfig = {
+	maxDurationSecs: number;
+};
+
+export type ReplayConfig = {
+	enabled: boolean;
+	device?: DeviceId;
+	loopback: boolean;
+	durationSecs: number;
+};
+
+export type RateLimitConfig = {
+	enabled: boolean;
+	maxPlays: number;
+	windowMs: number;
+};
+
+export type CooldownReason = "cooldown" | "rateLimit";
+
+export type TtsConfig = {
+	command: string;
+};
+
+export type ScheduleRule =
+	| { type: "interval"; everySecs: number }
+	| { type: "cron"; expression: string };
+
+export type Schedule = {
+	id?: string;
+	name: string;
+	sound: string;
+	rule: ScheduleRule;
+	enabled: boolean;
+};
+
 export type Config = {
 	globalVolume: number;
 	outputDevices: OutputDevice[];
+	cueOutputDevice?: OutputDevice;
+	limiter: LimiterConfig;
+	pluginsConfig: PluginsConfig;
+	pluginChains: PluginChainConfig[];
 	soundsConfig: SoundsConfig;
+	ttsConfig: TtsConfig;
+	recordingConfig: RecordingConfig;
+	replay: ReplayConfig;
+	rateLimit: RateLimitConfig;
 	sounds: Sound[];
+	soundGroups: SoundGroup[];
+	folderBoards: FolderBoard[];
+	schedules: Schedule[];
+};
+
+export type OutputLevels = {
+	device: DeviceId;
+	peak: number;
+	outputPeak: number;
+	rms: number;
+	gainReduction: number;
 };
@@ -1,11 +1,12 @@
 import changelog from "./changelog.js";
 import { SocketWrapper } from "./socketWrapper.js";
-import { Config, OutputDevice, Sound } from "./config.js";
-import { doesNameMatchSearch, generateShortId, isVersionNewer, setTheme, soundNameSearchPreprocess } from "./utils.js";
+import { Config, DeviceId, OutputDevice, Sound, SoundGroup, SoundImage } from "./config.js";
+import { deviceMatches, doesNameMatchSearch, drawLevels, formatDevice, generateShortId, isVersionNewer, setTheme, soundNameSearchPreprocess } from "./utils.js";
 
 $(() => {
 	let currentVersion = "0.0.0";
 	let config: Config = null;
+	let availableDevices: DeviceId[] = [];
 	const socket = new SocketWrapper("ws://127.0.0.1:29816");
 	const ignoreUpdates: boolean = JSON.parse(localStorage.getItem("ignoreUpdates") || "false");
 	const requestCallbacks: { [key: number]: (data) => void } = {};
@@ -19,24 +20,51 @@ $(() => {
 			socket.send({ "message": "outputDevices" });
 			socket.send({ "message": "isAutostartEnabled" });
 			socket.send({ "message": "currentVersion" });
+			socket.send({ "message": "subscribeLevels" });
 		})
+		.on("levels", (data) => drawLevels($("#output-levels"), data.levels))
 		.on("outputDevices", (data) => {
 			loadDevices(data.devices);
 			if (!config) {
 				socket.send({ "message": "config" });
 			}
 		})
-		.on("modifiedSound", (data) => editSound(data.previousPath, data.sound))
+		.on("outputDevicesChanged", (data) => {
+			loadDevices(data.devices);
+			if (config) {
+				config.outputDevices = data.config;
+				loadConfigDevices();
+				loadConfigCueDevice();
+			}
+		})
+		.on("modifiedSound", (data) => editSound(data.id, data.sound))
+		.on("soundImageCached", (data) => setCachedImage(data.id, data.image))
 		.on("addedSounds", (data) => addSounds(data.sounds))
-		.on("removedSound", (data) => removeSound(data.path))
+		.on("removedSound", (data) => removeSound(data.id))
 		.on("config", (data) => {
 			config = data.config;
 			loadConfig();
 		})
+		.on("configGlobalVolume", (data) => {
+			config.globalVolume = data.config;
+			loadConfigGlobalVolume();
+		})
+		.on("configLimiter", (data) => {
+			config.limiter = data.config;
+			loadConfigLimiter();
+		})
 		.on("configOutputDevices", (data) => {
 			config.outputDevices = data.config;
 			loadConfigDevices();
 		})
+		.on("configCueOutputDevice", (data) => {
+			config.cueOutputDevice = data.config;
+			loadConfigCueDevice();
+		})
+		.on("configSoundGroups", (data) => {
+			config.soundGroups = data.config;
+			loadConfigSoundGroups();
+		})
 		.on("configSoundsConfig", (data) => {
 			config.soundsConfig = data.config;
 			loadConfigSoundsConfig();
@@ -61,43 +89,155 @@ $(() => {
 		})
 		.on("error", (data) => console.error(data.error));
 
-	function loadDevices(devices: string[]): void {
+	function loadDevices(devices: DeviceId[]): void {
+		availableDevices = devices;
 		$("#output-devices").empty();
 		let i = 0;
 		for (const dev of devices) {
 			const $switch = $(
 				`<div class="field">
-					<input data-device="${dev}" id="output-device-${i}" type="checkbox" class="switch is-rounded is-info output-device">
-					<label for="output-device-${i}">${dev}</label>
+					<input data-device-index="${i}" id="output-device-${i}" type="checkbox" class="switch is-rounded is-info output-device">
+					<label for="output-device-${i}">${formatDevice(dev)} <span class="tag">${dev.host}</span></label>
 				</div>`
 			);
 			$switch.find(".output-device").on("change", () => {
 				const devices: OutputDevice[] = $(".output-device")
 					.filter((_idx, dev) => $(dev).prop("checked") === true)
 					.map((_idx, dev) => {
+						const id = availableDevices[$(dev).data("device-index")];
+						const configured = config.outputDevices.find(d => deviceMatches(d, id));
 						return {
-							"name": $(dev).data("device"),
-							"volume": 1.0,
+							...id,
+							"volume": configured ? configured.volume : 1.0,
 						};
 					})
 					.toArray();
+				// Keep disconnected devices selected so that they are used again when they come back
+				const missingDevices = config.outputDevices.filter(dev => !availableDevices.some(d => deviceMatches(d, dev)));
 				socket.send({
 					"message": "setOutputDevices",
-					"devices": devices,
+					"devices": devices.concat(missingDevices),
 				});
 			});
 			$switch.appendTo("#output-devices");
 			++i;
 		}
+
+		// The device lists of the sounds follow the devices being plugged in or out
+		$("#sounds .sound").each((_idx, el) => {
+			const sound = $(el).data("sound") as Sound | undefined;
+			if (sound) {
+				loadSoundDevices($(el), sound, () => socket.send({ "message": "editSound", "sound": sound }));
+			}
+		});
+
+		const $cue = $("#cue-output-device").empty();
+		$cue.append($("<option>").val("").text("None"));
+		devices.forEach((dev, idx) => {
+			$cue.append($("<option>").val(idx).text(`${formatDevice(dev)} (${dev.host})`));
+		});
 	}
 
 	function loadConfigDevices(): void {
 		$(".output-device").prop("checked", false);
+		$(".output-device-missing").remove();
 		for (const dev of config.outputDevices) {
-			$(`.output-device[data-device="${dev.name}"]`).prop("checked", true);
+			const idx = availableDevices.findIndex(d => deviceMatches(d, dev));
+			if (idx === -1) {
+				$(`<div class="field output-device-missing">
+					<span class="tag is-warning">Missing</span>&ensp;${formatDevice(dev)}
+				</div>`).appendTo("#output-devices");
+			} else {
+				$(`.output-device[data-device-index="${idx}"]`).prop("checked", true);
+			}
 		}
 	}
 
+	function loadConfigCueDevice(): void {
+		const cue = config.cueOutputDevice;
+		const idx = cue ? availableDevices.findIndex(d => deviceMatches(d, cue)) : -1;
+		$("#cue-output-device").val(idx === -1 ? "" : idx);
+	}
+
+	function loadConfigGlobalVolume(): void {
+		$("#input-global-volume").val(config.globalVolume);
+	}
+
+	function loadConfigLimiter(): void {
+		$("#input-limiter-enabled").prop("checked", config.limiter.enabled);
+		$("#input-limiter-threshold").val(config.limiter.thresholdDb);
+	}
+
+	function sendSoundGroups(groups: SoundGroup[]): void {
+		socket.send({
+			"message": "setSoundGroups",
+			"groups": groups,
+		});
+	}
+
+	function loadConfigSoundGroups(): void {
+		const $groups = $("#sound-groups").empty();
+		config.soundGroups.forEach((group, idx) => {
+			const $group = $(
+				`<div class="box sound-group">
+					<div class="field has-addons">
+						<div class="control is-expanded">
+							<input class="input sound-group-input-name" type="text" placeholder="Name">
+						</div>
+						<div class="control">
+							<a class="button is-info sound-group-btn-play"><i class="fas fa-random"></i>&ensp;Play</a>
+						</div>
+						<div class="control">
+							<a class="button is-danger sound-group-btn-delete"><i class="fas fa-trash"></i></a>
+						</div>
+					</div>
+					<div class="field">
+						<input id="sound-group-avoid-repeats-${idx}" type="checkbox" class="switch is-rounded is-info sound-group-input-avoid-repeats">
+						<label for="sound-group-avoid-repeats-${idx}">Play every sound once before repeating</label>
+					</div>
+					<span class="sound-group-count"></span>
+				</div>`
+			);
+			$group.find(".sound-group-input-name").val(group.name).on("change", (e) => {
+				const groups = config.soundGroups.slice();
+				groups[idx] = { ...group, "name": $(e.currentTarget).val() as string };
+				sendSoundGroups(groups);
+			});
+			$group.find(".sound-group-input-avoid-repeats").prop("checked", group.avoidRepeats).on("change", (e) => {
+				const groups = config.soundGroups.slice();
+				groups[idx] = { ...group, "avoidRepeats": $(e.currentTarget).prop("checked") as boolean };
+				sendSoundGroups(groups);
+			});
+			$group.find(".sound-group-btn-play").on("click", () => {
+				socket.send({
+					"message": "playGroup",
+					"group": group.name,
+				});
+			});
+			$group.find(".sound-group-btn-delete").on("click", () => {
+				sendSoundGroups(config.soundGroups.filter((_group, i) => i !== idx));
+			});
+			$group.find(".sound-group-count").text(`${group.sounds.length} sound${group.sounds.length === 1 ? "" : "s"}`);
+			$group.appendTo($groups);
+		});
+
+		$("#sounds .sound").each((_idx, el) => {
+			const sound = $(el).data("sound") as Sound | undefined;
+			if (sound) {
+				loadSoundGroups($(el), sound);
+			}
+		});
+	}
+
+	// Groups are played by name, so a new group gets a name that is not taken
+	function addSoundGroup(): void {
+		let n = config.soundGroups.length + 1;
+		while (config.soundGroups.some(group => group.name === `Group ${n}`)) {
+			++n;
+		}
+		sendSoundGroups(config.soundGroups.concat([{ "name": `Group ${n}`, "sounds": [], "avoidRepeats": true }]));
+	}
+
 	function loadConfigSoundsConfig(): void {
 		$("#input-copy-sound").prop("checked", config.soundsConfig.copyFile);
 	}
@@ -112,9 +252,13 @@ $(() => {
 	}
 
 	function loadConfig(): void {
+		loadConfigGlobalVolume();
+		loadConfigLimiter();
 		loadConfigDevices();
+		loadConfigCueDevice();
 		loadConfigSoundsConfig();
 		loadConfigSounds();
+		loadConfigSoundGroups();
 	}
 
 	function setAutostart(enabled: boolean): void {
@@ -132,7 +276,8 @@ $(() => {
 		const $sound = $("#sound-template")
 			.clone()
 			.removeAttr("id")
-			.attr("data-sound-path", sound.path);
+			.attr("data-sound-id", sound.id)
+			.data("sound", sound);
 		$sound.find(".sound-input-name").val(sound.name);
 		$sound.find(".sound-input-path").val(sound.path);
 
@@ -141,25 +286,26 @@ $(() => {
 			sendSound();
 		});
 
+		$sound.find(".sound-input-volume").val(sound.volume);
+		$sound.find(".sound-input-volume").on("input", () => {
+			sound.volume = parseFloat($sound.find(".sound-input-volume").val() as string);
+			sendSound();
+		});
+
 		$sound.find(".sound-input-path").on("input", () => {
-			const prevPath = sound.path;
 			sound.path = $sound.find(".sound-input-path").val() as string;
-			$sound.attr("data-sound-path", sound.path);
-			socket.send({
-				"message": "editSound",
-				"previousPath": prevPath,
-				"sound": sound,
-			});
+			sendSound();
 		});
 
+		loadSoundDevices($sound, sound, sendSound);
+		loadSoundGroups($sound, sound);
+
 		$sound.find(".button-browse-sound").on("click", () => {
 			const reqId = parseInt(generateShortId(), 36);
 			requestCallbacks[reqId] = (data) => {
-				const prevPath = sound.path;
 				sound.path = data.newPath;
 				socket.send({
 					"message": "editSound",
-					"previousPath": prevPath,
 					"sound": sound,
 					"receiveUpdate": true,
 				});
@@ -209,7 +355,9 @@ $(() => {
 			$sound.find(".sound-checkbox-image").prop("checked", "checked");
 			$sound.find(".sound-image-preview").show();
 			$sound.find("input[type=radio]").parent().show();
-			if (sound.image.file) {
+			if (sound.image.cached) {
+				$sound.find(".sound-image-preview").attr("src", `http://127.0.0.1:29815/thumbnails/${sound.image.cached}`);
+			} else if (sound.image.file) {
 				$sound.find(".sound-input-image-type-file")
 					.prop("checked", "checked")
 					.trigger("change");
@@ -224,6 +372,13 @@ $(() => {
 			}
 		}
 
+		$sound.find(".sound-btn-preview").on("click", () => {
+			socket.send({
+				"message": "previewSound",
+				"sound": sound,
+			});
+		});
+
 		$sound.find(".sound-btn-delete").on("click", () => {
 			$("#modal-delete-sound").find(".sound-name").text(sound.name);
 			$("#modal-delete-sound").addClass("is-active");
@@ -235,7 +390,7 @@ $(() => {
 
 				socket.send({
 					"message": "deleteSound",
-					"sound": sound.path,
+					"sound": sound.id,
 				});
 			});
 		});
@@ -243,9 +398,74 @@ $(() => {
 		return $sound;
 	}
 
-	function editSound(prevPath: string, newSound: Sound): void {
+	// A sound without a device list plays on every enabled output device
+	function loadSoundDevices($sound: JQuery<HTMLElement>, sound: Sound, sendSound: () => void): void {
+		const $devices = $sound.find(".sound-output-devices").empty();
+		$sound.find(".sound-checkbox-all-devices")
+			.prop("checked", !sound.outputDevices)
+			.off("change")
+			.on("change", (e) => {
+				sound.outputDevices = $(e.currentTarget).prop("checked") ? null : [];
+				loadSoundDevices($sound, sound, sendSound);
+				sendSound();
+			});
+		if (!sound.outputDevices) {
+			return;
+		}
+
+		// Disconnected devices stay listed so that they can be unchecked
+		const missingDevices = sound.outputDevices.filter(id => !availableDevices.some(d => deviceMatches(d, id)));
+		for (const dev of availableDevices.concat(missingDevices)) {
+			const missing = missingDevices.indexOf(dev) !== -1;
+			const $device = $(
+				`<div>
+					<label class="checkbox">
+						<input type="checkbox"> ${formatDevice(dev)} <span class="tag">${dev.host}</span>
+						${missing ? `<span class="tag is-warning">Missing</span>` : ""}
+					</label>
+				</div>`
+			);
+			$device.find("input")
+				.prop("checked", sound.outputDevices.some(id => deviceMatches(id, dev)))
+				.on("change", (e) => {
+					sound.outputDevices = sound.outputDevices.filter(id => !deviceMatches(id, dev));
+					if ($(e.currentTarget).prop("checked")) {
+						sound.outputDevices.push({ "host": dev.host, "name": dev.name, "index": dev.index });
+					}
+					sendSound();
+				});
+			$device.appendTo($devices);
+		}
+	}
+
+	function loadSoundGroups($sound: JQuery<HTMLElement>, sound: Sound): void {
+		const $groups = $sound.find(".sound-groups").empty();
+		$sound.find(".sound-groups-field").toggle(config.soundGroups.length !== 0);
+		config.soundGroups.forEach((group, idx) => {
+			const $group = $(
+				`<label class="checkbox">
+					<input type="checkbox">
+				</label>`
+			);
+			$group.append(document.createTextNode(` ${group.name}`));
+			$group.find("input")
+				.prop("checked", group.sounds.indexOf(sound.id) !== -1)
+				.on("change", (e) => {
+					const sounds = group.sounds.filter(id => id !== sound.id);
+					if ($(e.currentTarget).prop("checked")) {
+						sounds.push(sound.id);
+					}
+					const groups = config.soundGroups.slice();
+					groups[idx] = { ...group, sounds };
+					sendSoundGroups(groups);
+				});
+			$("<div>").append($group).appendTo($groups);
+		});
+	}
+
+	function editSound(id: string, newSound: Sound): void {
 		const $sounds = $(".sound");
-		const sound = $sounds.toArray().find(s => $(s).data("sound-path") === prevPath);
+		const sound = $sounds.toArray().find(s => $(s).data("sound-id") === id);
 		if (!sound) {
 			return;
 		}
@@ -253,6 +473,17 @@ $(() => {
 		$sound.replaceWith(buildSound(newSound));
 	}
 
+	// The server sends the cached copy of an image once it is downloaded, later edits must send it instead of the URL
+	function setCachedImage(id: string, image: SoundImage): void {
+		const $sound = $("#sounds .sound").filter((_idx, el) => $(el).data("sound-id") === id);
+		const sound = $sound.data("sound") as Sound | undefined;
+		if (!sound) {
+			return;
+		}
+		sound.image = image;
+		$sound.find(".sound-image-preview").attr("src", `http://127.0.0.1:29815/thumbnails/${image.cached}`);
+	}
+
 	function addSounds(sounds: Sound[]): void {
 		const search = getSearchFilter();
 
@@ -265,10 +496,10 @@ $(() => {
 		}
 	}
 
-	function removeSound(path: string): void {
+	function removeSound(id: string): void {
 		$("#sounds .sound").each((_idx, el) => {
 			const $el = $(el);
-			if ($el.data("sound-path") === path) {
+			if ($el.data("sound-id") === id) {
 				$el.remove();
 			}
 		});
@@ -364,10 +595,39 @@ $(() => {
 		localStorage.setItem("ignoreUpdates", JSON.stringify(true));
 	});
 
+	$(".btn-add-sound-group").on("click", addSoundGroup);
+
 	$("#input-autostart").on("change", () => {
 		socket.send({ "message": "setAutostart", "enabled": $("#input-autostart").prop("checked") });
 	});
 
+	// The volume is applied while the slider moves, but only saved once it is released
+	$("#input-global-volume").on("input change", (e) => {
+		socket.send({
+			"message": "setGlobalVolume",
+			"volume": parseFloat($("#input-global-volume").val() as string),
+			"save": e.type === "change",
+		});
+	});
+
+	$("#input-limiter-enabled, #input-limiter-threshold").on("change", () => {
+		socket.send({
+			"message": "setLimiter",
+			"limiter": {
+				"enabled": $("#input-limiter-enabled").prop("checked") as boolean,
+				"thresholdDb": parseFloat($("#input-limiter-threshold").val() as string),
+			},
+		});
+	});
+
+	$("#cue-output-device").on("change", () => {
+		const idx = $("#cue-output-device").val() as string;
+		socket.send({
+			"message": "setCueOutputDevice",
+			"device": idx === "" ? null : { ...availableDevices[parseInt(idx)], "volume": 1.0 },
+		});
+	});
+
 	$("#input-copy-sound").on("change", () => {
 		socket.send({
 			"message": "setCopySound",
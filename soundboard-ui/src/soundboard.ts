@@ -1,6 +1,6 @@
-import { Sound } from "./config.js";
+import { CooldownReason, Sound, SoundGroup } from "./config.js";
 import { SocketWrapper } from "./socketWrapper.js";
-import { setTheme, soundNameSearchPreprocess, doesNameMatchSearch } from "./utils.js";
+import { setTheme, soundNameSearchPreprocess, doesNameMatchSearch, drawLevels } from "./utils.js";
 
 $(() => {
 	onWindowOpened();
@@ -10,11 +10,19 @@ $(() => {
 		.onOpen(() => {
 			socket.send({ "message": "clientType", "clientType": "soundboard" });
 			socket.send({ "message": "sounds" });
+			socket.send({ "message": "recentlyPlayed" });
+			socket.send({ "message": "soundGroups" });
+			socket.send({ "message": "subscribeLevels" });
 		})
+		.on("levels", (data) => drawLevels($("#output-levels"), data.levels))
 		.on("sounds", (data) => loadSounds(data.sounds))
-		.on("modifiedSound", (data) => editSound(data.previousPath, data.sound))
+		.on("recentlyPlayed", (data) => loadRecentSounds(data.sounds))
+		.on("soundGroups", (data) => loadSoundGroups(data.groups))
+		.on("configSoundGroups", (data) => loadSoundGroups(data.config))
+		.on("modifiedSound", (data) => editSound(data.id, data.sound))
 		.on("addedSounds", (data) => addSounds(data.sounds))
-		.on("removedSound", (data) => removeSound(data.path))
+		.on("removedSound", (data) => removeSound(data.id))
+		.on("soundOnCooldown", (data) => showCooldown(data.id, data.reason, data.remainingMs))
 		.on("theme", (data) => setTheme(data.theme))
 		.on("soundboardOpened", onWindowOpened)
 		.on("error", (data) => console.error(data.error));
@@ -62,7 +70,8 @@ $(() => {
 			if ($selected.length !== 0) {
 				socket.send({
 					"message": "playSound",
-					"sound": $selected.data("sound-path"),
+					"sound": $selected.data("sound-id"),
+					"source": "hotkey",
 				});
 			}
 		}
@@ -77,9 +86,11 @@ $(() => {
 	function buildSound(sound: Sound): JQuery<HTMLElement> {
 		const $box = $("<div>").addClass("box sound");
 		$box.attr("data-sound-name", sound.name);
-		$box.attr("data-sound-path", sound.path);
+		$box.attr("data-sound-id", sound.id);
 		if (sound.image) {
-			if (sound.image.url) {
+			if (sound.image.cached) {
+				$box.css("--background", `url(http://127.0.0.1:29815/images/${sound.image.cached})`);
+			} else if (sound.image.url) {
 				$box.css("--background", `url(${sound.image.url})`);
 			} else if (sound.image.file) {
 				// TODO
@@ -94,21 +105,74 @@ $(() => {
 		}).on("click", () => {
 			socket.send({
 				"message": "playSound",
-				"sound": sound.path,
+				"sound": sound.id,
 			});
 		});
 		$box.appendTo("#all-sounds");
 		return $box;
 	}
 
+	const cooldownTimers = new Map<string, number>();
+	let rateLimitTimer: number | undefined;
+
+	// Greys the sound out until it can be played again, or the whole board when the rate limit is reached
+	function showCooldown(id: string, reason: CooldownReason, remainingMs: number): void {
+		if (reason === "rateLimit") {
+			$("#sounds").addClass("rate-limited");
+			window.clearTimeout(rateLimitTimer);
+			rateLimitTimer = window.setTimeout(() => $("#sounds").removeClass("rate-limited"), remainingMs);
+			return;
+		}
+
+		const $box = $(`#sounds .sound[data-sound-id="${id}"]`).addClass("on-cooldown");
+		window.clearTimeout(cooldownTimers.get(id));
+		cooldownTimers.set(id, window.setTimeout(() => {
+			$box.removeClass("on-cooldown");
+			cooldownTimers.delete(id);
+		}, remainingMs));
+	}
+
 	function loadSounds(sounds: Sound[]) {
 		const $sounds = sounds.map(sound => buildSound(sound));
 		buildSoundsGrid($sounds);
 	}
 
-	function editSound(prevPath: string, newSound: Sound): void {
+	function loadRecentSounds(sounds: Sound[]): void {
+		const $recent = $("#recent-sounds").empty();
+		for (const sound of sounds) {
+			$("<span>")
+				.addClass("tag is-medium recent-sound")
+				.text(sound.name)
+				.on("click", () => {
+					socket.send({
+						"message": "playSound",
+						"sound": sound.id,
+					});
+				})
+				.appendTo($recent);
+		}
+	}
+
+	// Each group plays one of its sounds at random
+	function loadSoundGroups(groups: SoundGroup[]): void {
+		const $groups = $("#sound-groups").empty();
+		for (const group of groups.filter(group => group.sounds.length !== 0)) {
+			$("<span>")
+				.addClass("tag is-medium is-info sound-group")
+				.text(group.name)
+				.on("click", () => {
+					socket.send({
+						"message": "playGroup",
+						"group": group.name,
+					});
+				})
+				.appendTo($groups);
+		}
+	}
+
+	function editSound(id: string, newSound: Sound): void {
 		const $sounds = $(".sound");
-		const sound = $sounds.toArray().find(s => $(s).data("sound-path") === prevPath);
+		const sound = $sounds.toArray().find(s => $(s).data("sound-id") === id);
 		if (!sound) {
 			return;
 		}
@@ -136,9 +200,9 @@ $(() => {
 		}
 	}
 
-	function removeSound(path: string): void {
+	function removeSound(id: string): void {
 		const $sounds = $(".sound");
-		const sound = $sounds.toArray().find(s => $(s).data("sound-path") === path);
+		const sound = $sounds.toArray().find(s => $(s).data("sound-id") === id);
 		if (!sound) {
 			return;
 		}
@@ -1,3 +1,5 @@
+import { DeviceId, OutputLevels } from "./config.js";
+
 export const soundNameSearchPreprocess = (s: string): string => {
 	s = s.normalize("NFD").replace(/[\u0300-\u036f]/g, ""); // Remove accents and diacritics
 	s = s.toLowerCase();
@@ -44,3 +46,34 @@ export const isVersionNewer = (currentVersion: string, testVersion: string): boo
 	}
 	return testSplit[2] > currentSplit[2];
 }
+
+export const deviceMatches = (a: DeviceId, b: DeviceId): boolean => {
+	// An empty host comes from a configuration saved before devices were identified by host
+	const sameHost = a.host === "" || b.host === "" || a.host === b.host;
+	return sameHost && a.name === b.name && a.index === b.index;
+}
+
+export const formatDevice = (dev: DeviceId): string => {
+	let s = dev.name;
+	if (dev.index > 0) {
+		s += ` #${dev.index + 1}`;
+	}
+	return s;
+}
+
+const levelToMeter = (level: number): number => {
+	// Display levels from -60 dB to 0 dB
+	const db = 20 * Math.log10(Math.max(level, 1e-6));
+	return Math.min(Math.max((db + 60) / 60, 0), 1);
+}
+
+export const drawLevels = ($container: JQuery<HTMLElement>, levels: OutputLevels[]): void => {
+	$container.empty();
+	for (const level of levels) {
+		const limiting = level.gainReduction < 0.999;
+		$(`<div class="output-level">
+			<span class="output-level-name">${formatDevice(level.device)}</span>
+			<progress class="progress is-small ${limiting ? "is-danger" : "is-info"}" max="1" value="${levelToMeter(level.outputPeak)}"></progress>
+		</div>`).appendTo($container);
+	}
+}
@@ -0,0 +1,218 @@
+use actix_files::NamedFile;
+use actix_web::{ web, error, HttpResponse, error::BlockingError, http::header };
+use actix_web::dev::{ Service, ServiceRequest };
+
+use crate::config::Sound;
+use crate::stats::PlaySource;
+
+type AppData = web::Data<crate::App>;
+
+// Versioned so that integrations keep working when the API changes
+pub fn configure(cfg: &mut web::ServiceConfig) {
+	cfg.service(
+		web::scope("/api/v1")
+			.wrap_fn(|req, srv| {
+				let call = if is_local_host(&req) { Ok(srv.call(req)) } else { Err(req) };
+				async move {
+					match call {
+						Ok(call) => call.await,
+						Err(req) => Ok(req.into_response(HttpResponse::Forbidden().body("Unknown host"))),
+					}
+				}
+			})
+			.route("/sounds", web::get().to(sounds))
+			.route("/sounds/{id:.+}/play", web::post().to(play_sound))
+			.route("/sounds/{id:.+}/audio", web::get().to(sound_audio))
+			.route("/sounds/{id:.+}", web::get().to(sound))
+			.route("/sounds/{id:.+}", web::put().to(edit_sound))
+	);
+}
+
+// A page that rebinds its own domain to 127.0.0.1 still sends that domain as the Host, so it cannot use the API
+fn is_local_host(req: &ServiceRequest) -> bool {
+	match req.headers().get(header::HOST).and_then(|host| host.to_str().ok()) {
+		Some(host) => host == "127.0.0.1:29815" || host == "localhost:29815",
+		None => false,
+	}
+}
+
+fn not_found() -> actix_web::Error {
+	error::ErrorNotFound("Unknown sound")
+}
+
+fn canceled() -> actix_web::Error {
+	error::ErrorInternalServerError("The request was canceled")
+}
+
+fn find_sound(app: &crate::App, id: &str) -> actix_web::Result<Sound> {
+	app.config
+		.lock().unwrap()
+		.find_sound(id)
+		.cloned()
+		.ok_or_else(not_found)
+}
+
+async fn sounds(app: AppData) -> HttpResponse {
+	HttpResponse::Ok().json(&app.config.lock().unwrap().sounds)
+}
+
+async fn sound(app: AppData, id: web::Path<String>) -> actix_web::Result<HttpResponse> {
+	Ok(HttpResponse::Ok().json(find_sound(&app, &id)?))
+}
+
+// The server lock can be held for a while, e.g. during a plugin scan, so it is never waited for on the async workers
+async fn play_sound(app: AppData, id: web::Path<String>) -> actix_web::Result<HttpResponse> {
+	let sound = find_sound(&app, &id)?;
+	let played = web::block(move || app.websocket_server.lock().unwrap().play_sound_by_id(&sound.id, PlaySource::Api)).await;
+	match played {
+		Ok(true) => Ok(HttpResponse::NoContent().finish()),
+		Ok(false) => Err(not_found()),
+		Err(BlockingError::Canceled) => Err(canceled()),
+		Err(BlockingError::Error(blocked)) => Ok(HttpResponse::TooManyRequests()
+			.header("Retry-After", (blocked.remaining.as_millis() as u64 + 999) / 1000)
+			.json(serde_json::json!({
+				"error": blocked.to_string(),
+				"reason": blocked.reason,
+				"remainingMs": blocked.remaining.as_millis() as u64,
+			}))),
+	}
+}
+
+// Fields missing from the body keep their current value
+async fn edit_sound(app: AppData, id: web::Path<String>, body: web::Json<serde_json::Value>) -> actix_web::Result<HttpResponse> {
+	let sound = find_sound(&app, &id)?;
+	let mut merged = serde_json::to_value(&sound)?;
+	match (merged.as_object_mut(), body.into_inner()) {
+		(Some(merged), serde_json::Value::Object(fields)) => merged.extend(fields),
+		_ => return Err(error::ErrorBadRequest("The body must be a JSON object")),
+	}
+	let sound_edited: Sound = serde_json::from_value(merged).map_err(error::ErrorBadRequest)?;
+
+	let saved = web::block(move || app.websocket_server.lock().unwrap().edit_sound_by_id(&sound.id, sound_edited)).await;
+	match saved {
+		Ok(Some(sound)) => Ok(HttpResponse::Ok().json(sound)),
+		Ok(None) => Err(not_found()),
+		Err(BlockingError::Canceled) => Err(canceled()),
+		Err(BlockingError::Error(err)) => Err(error::ErrorBadRequest(err)),
+	}
+}
+
+// NamedFile answers Range requests, so browsers can seek while previewing
+async fn sound_audio(app: AppData, id: web::Path<String>) -> actix_web::Result<NamedFile> {
+	let sound = find_sound(&app, &id)?;
+	Ok(NamedFile::open(&sound.path)?)
+}
+
+#[cfg(test)]
+fn test_app(config: crate::config::Config) -> std::sync::Arc<crate::App> {
+	use std::sync::{ Arc, Mutex };
+
+	let app = Arc::new(crate::App {
+		args: crate::Args::default(),
+		config: Mutex::new(config),
+		stats: Mutex::new(crate::stats::PlayStats::default()),
+		output_buses: crate::output_bus::OutputBuses::default(),
+		update_available: None,
+		web_server: Arc::default(),
+		device_monitor: Arc::default(),
+		replay: Arc::default(),
+		folder_watcher: Arc::default(),
+		scheduler: Arc::default(),
+		websocket_server: Arc::default(),
+		window_manager: Arc::default(),
+	});
+	app.websocket_server.lock().unwrap().set_app(app.clone());
+	app
+}
+
+#[cfg(test)]
+#[actix_rt::test]
+async fn test_unknown_sounds_are_not_found() {
+	use actix_web::test::{ call_service, init_service, TestRequest };
+
+	let app = test_app(crate::config::Config::default());
+	let mut service = init_service(actix_web::App::new().app_data(web::Data::from(app.clone())).configure(configure)).await;
+	let requests = vec![
+		TestRequest::get().uri("/api/v1/sounds/nope"),
+		TestRequest::put().uri("/api/v1/sounds/nope").set_json(&serde_json::json!({ "name": "x" })),
+		TestRequest::post().uri("/api/v1/sounds/nope/play"),
+		TestRequest::get().uri("/api/v1/sounds/nope/audio"),
+	];
+	for request in requests {
+		let response = call_service(&mut service, request.header("Host", "127.0.0.1:29815").to_request()).await;
+		assert_eq!(response.status(), actix_web::http::StatusCode::NOT_FOUND);
+	}
+}
+
+#[cfg(test)]
+#[actix_rt::test]
+async fn test_play_sound_is_rate_limited() {
+	use actix_web::test::{ call_service, init_service, TestRequest };
+
+	// Plays of missing files are not counted, so the sound needs a real file
+	let path = std::env::temp_dir().join(format!("soundboard-api-test-{}-horn.mp3", std::process::id()));
+	std::fs::write(&path, b"").unwrap();
+	let mut config = crate::config::Config::default();
+	config.sounds.push(Sound::new("Air horn".to_owned(), path.to_string_lossy().into_owned()));
+	config.rate_limit = crate::config::RateLimitConfig {
+		enabled: true,
+		max_plays: 1,
+		window_ms: 60_000,
+	};
+	let id = config.sounds[0].id.clone();
+	let app = test_app(config);
+	let mut service = init_service(actix_web::App::new().app_data(web::Data::from(app.clone())).configure(configure)).await;
+
+	let play = || TestRequest::post().uri(&format!("/api/v1/sounds/{}/play", id)).header("Host", "localhost:29815").to_request();
+	let response = call_service(&mut service, play()).await;
+	assert_eq!(response.status(), actix_web::http::StatusCode::NO_CONTENT);
+	let response = call_service(&mut service, play()).await;
+	assert_eq!(response.status(), actix_web::http::StatusCode::TOO_MANY_REQUESTS);
+	assert_eq!(response.headers().get("Retry-After").unwrap(), "60");
+	assert_eq!(app.stats.lock().unwrap().play_count(&id), 1);
+
+	let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(test)]
+#[actix_rt::test]
+async fn test_sound_audio_supports_range_requests() {
+	use actix_web::test::{ call_service, init_service, read_body, TestRequest };
+
+	let path = std::env::temp_dir().join(format!("soundboard-api-test-{}.wav", std::process::id()));
+	std::fs::write(&path, b"0123456789").unwrap();
+	let mut config = crate::config::Config::default();
+	config.sounds.push(Sound::new("Clip".to_owned(), path.to_string_lossy().into_owned()));
+	let id = config.sounds[0].id.clone();
+	let app = test_app(config);
+	let mut service = init_service(actix_web::App::new().app_data(web::Data::from(app.clone())).configure(configure)).await;
+
+	let request = TestRequest::get()
+		.uri(&format!("/api/v1/sounds/{}/audio", id))
+		.header("Host", "127.0.0.1:29815")
+		.header("Range", "bytes=2-5")
+		.to_request();
+	let response = call_service(&mut service, request).await;
+	assert_eq!(response.status(), actix_web::http::StatusCode::PARTIAL_CONTENT);
+	assert_eq!(read_body(response).await, web::Bytes::from_static(b"2345"));
+
+	let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(test)]
+#[actix_rt::test]
+async fn test_other_hosts_are_forbidden() {
+	use actix_web::test::{ call_service, init_service, TestRequest };
+
+	let mut config = crate::config::Config::default();
+	config.sounds.push(Sound::new("Secret".to_owned(), "C:/secret.txt".to_owned()));
+	let id = config.sounds[0].id.clone();
+	let app = test_app(config);
+	let mut service = init_service(actix_web::App::new().app_data(web::Data::from(app.clone())).configure(configure)).await;
+
+	let audio = || TestRequest::get().uri(&format!("/api/v1/sounds/{}/audio", id));
+	for request in vec![audio(), audio().header("Host", "rebind.example:29815")] {
+		let response = call_service(&mut service, request.to_request()).await;
+		assert_eq!(response.status(), actix_web::http::StatusCode::FORBIDDEN);
+	}
+}
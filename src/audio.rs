@@ -1,60 +1,236 @@
-use std::io::BufReader;
+use std::{
+	io::BufReader,
+	path::Path,
+	time::Duration,
+	sync::{
+		Arc, Mutex,
+		atomic::{ AtomicBool, AtomicU32, Ordering },
+	},
+};
 
 use anyhow::Result;
+use rodio::Source;
 use cpal::traits::{DeviceTrait, HostTrait};
 
-pub struct PlaySound {
-	pub sound: rodio::Sink,
-	_stream: rodio::OutputStream,
-	_stream_handle: rodio::OutputStreamHandle,
+use crate::effects;
+use crate::output_bus::OutputBuses;
+use crate::config::{ DeviceId, Sound };
+
+struct PlaybackState {
+	volume: AtomicU32,
+	stop: AtomicBool,
+	done: AtomicBool,
+}
+
+// Shared between the thread that controls a sound and the audio thread that plays it
+#[derive(Clone)]
+pub struct PlaybackHandle(Arc<PlaybackState>);
+
+impl PlaybackHandle {
+	pub fn new(volume: f32) -> Self {
+		Self(Arc::new(PlaybackState {
+			volume: AtomicU32::new(volume.max(0.0).to_bits()),
+			stop: AtomicBool::new(false),
+			done: AtomicBool::new(false),
+		}))
+	}
+
+	pub fn volume(&self) -> f32 {
+		f32::from_bits(self.0.volume.load(Ordering::Relaxed))
+	}
+
+	pub fn set_volume(&self, volume: f32) {
+		self.0.volume.store(volume.max(0.0).to_bits(), Ordering::Relaxed);
+	}
+
+	pub fn stop(&self) {
+		self.0.stop.store(true, Ordering::Relaxed);
+	}
+
+	pub fn is_done(&self) -> bool {
+		self.0.done.load(Ordering::Relaxed)
+	}
+}
+
+// Applies the volume of a PlaybackHandle, ramping towards new values to avoid zipper noise
+pub struct PlaybackSource<S> {
+	input: S,
+	handle: PlaybackHandle,
+	current: f32,
+	channel: u16,
 }
 
-impl PlaySound {
-	pub fn new(stream: rodio::OutputStream, stream_handle: rodio::OutputStreamHandle, sink: rodio::Sink) -> Self {
+impl<S> PlaybackSource<S> {
+	fn ramp_duration() -> f32 {
+		0.05 // Seconds to go from silence to full volume
+	}
+
+	pub fn new(input: S, handle: PlaybackHandle) -> Self {
 		Self {
-			sound: sink,
-			_stream: stream,
-			_stream_handle: stream_handle,
+			input,
+			current: handle.volume(),
+			handle,
+			channel: 0,
+		}
+	}
+}
+
+impl<S> Iterator for PlaybackSource<S>
+where S: Source<Item = f32> {
+	type Item = f32;
+
+	fn next(&mut self) -> Option<f32> {
+		// Only update the gain on frame boundaries so that all channels get the same gain
+		if self.channel == 0 {
+			let stopping = self.handle.0.stop.load(Ordering::Relaxed);
+			if stopping && self.current <= 0.0 {
+				return None;
+			}
+
+			let target = if stopping { 0.0 } else { self.handle.volume() };
+			let max_step = 1.0 / (self.input.sample_rate() as f32 * Self::ramp_duration());
+			let diff = target - self.current;
+			self.current += diff.max(-max_step).min(max_step);
+		}
+		self.channel = (self.channel + 1) % self.input.channels().max(1);
+
+		let sample = self.input.next()?;
+		Some(sample * self.current)
+	}
+}
+
+impl<S> Source for PlaybackSource<S>
+where S: Source<Item = f32> {
+	fn current_frame_len(&self) -> Option<usize> {
+		self.input.current_frame_len()
+	}
+
+	fn channels(&self) -> u16 {
+		self.input.channels()
+	}
+
+	fn sample_rate(&self) -> u32 {
+		self.input.sample_rate()
+	}
+
+	fn total_duration(&self) -> Option<Duration> {
+		self.input.total_duration()
+	}
+}
+
+impl<S> Drop for PlaybackSource<S> {
+	fn drop(&mut self) {
+		self.handle.0.done.store(true, Ordering::Relaxed);
+	}
+}
+
+pub struct ActivePlayback {
+	pub sound_id: String,
+	pub device: DeviceId,
+	pub preview: bool,
+	pub handle: PlaybackHandle,
+}
+
+// Keeps track of the sounds that are playing so that their volume can be changed on the fly
+#[derive(Default)]
+pub struct Playbacks {
+	active: Mutex<Vec<ActivePlayback>>,
+}
+
+impl Playbacks {
+	pub fn register(&self, playback: ActivePlayback) {
+		let mut active = self.active.lock().unwrap();
+		active.retain(|playback| !playback.handle.is_done());
+		active.push(playback);
+	}
+
+	pub fn for_each(&self, mut f: impl FnMut(&ActivePlayback)) {
+		let mut active = self.active.lock().unwrap();
+		active.retain(|playback| !playback.handle.is_done());
+		for playback in active.iter() {
+			f(playback);
 		}
 	}
 }
 
-pub fn get_output_devices() -> Result<Vec<cpal::Device>> {
-	let mut devices = Vec::new();
+pub struct AudioDevice {
+	pub id: DeviceId,
+	pub device: cpal::Device,
+}
+
+pub fn supported_extensions() -> &'static [&'static str] {
+	&["mp3", "wav", "ogg", "flac"]
+}
+
+pub fn is_supported_file(path: &Path) -> bool {
+	match path.extension() {
+		Some(ext) => supported_extensions().contains(&ext.to_string_lossy().to_lowercase().as_str()),
+		None => false,
+	}
+}
+
+fn get_devices(is_usable: impl Fn(&cpal::Device) -> bool) -> Result<Vec<AudioDevice>> {
+	let mut devices: Vec<AudioDevice> = Vec::new();
 
 	for host_id in cpal::available_hosts() {
 		let host = cpal::host_from_id(host_id)?;
+		let host_name = host_id.name().to_owned();
 
-		devices.extend(
-			host.devices()?
-				.filter(|device| device.default_output_config().is_ok()),
-		);
+		for device in host.devices()?.filter(|device| is_usable(device)) {
+			let name = match device.name() {
+				Ok(name) => name,
+				Err(_) => continue,
+			};
+			let index = devices
+				.iter()
+				.filter(|dev| dev.id.host == host_name && dev.id.name == name)
+				.count();
+
+			devices.push(AudioDevice {
+				id: DeviceId {
+					host: host_name.clone(),
+					name,
+					index,
+				},
+				device,
+			});
+		}
 	}
 
 	Ok(devices)
 }
 
-pub fn play_sound(sound_path: &str, device_name: &str) -> Result<PlaySound> {
-	let devices = get_output_devices()?;
-	let device = devices
-		.iter()
-		.find(|device| device.name().unwrap() == device_name)
-		.ok_or_else(|| {
-			std::io::Error::new(
-				std::io::ErrorKind::Other,
-				format!("Device {} not found", device_name),
-			)
-		})?;
-	play_sound_on_device(sound_path, device)
+pub fn get_output_devices() -> Result<Vec<AudioDevice>> {
+	get_devices(|device| device.default_output_config().is_ok())
 }
 
-pub fn play_sound_on_device(sound_path: &str, device: &cpal::Device) -> Result<PlaySound> {
-	let (stream, stream_handle) = rodio::OutputStream::try_from_device(device)?;
+pub fn get_input_devices() -> Result<Vec<AudioDevice>> {
+	get_devices(|device| device.default_input_config().is_ok())
+}
+
+pub fn get_output_device_ids() -> Result<Vec<DeviceId>> {
+	let ids = get_output_devices()?
+		.into_iter()
+		.map(|dev| dev.id)
+		.collect();
+
+	Ok(ids)
+}
+
+pub fn get_input_device_ids() -> Result<Vec<DeviceId>> {
+	let ids = get_input_devices()?
+		.into_iter()
+		.map(|dev| dev.id)
+		.collect();
+
+	Ok(ids)
+}
 
-	let file = std::fs::File::open(sound_path)?;
-	let sound = stream_handle
-		.play_once(BufReader::new(file))
-		.map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, format!("{:?}", err)))?;
+pub fn play_sound(buses: &OutputBuses, sound: &Sound, device_id: &DeviceId, handle: PlaybackHandle) -> Result<()> {
+	let file = std::fs::File::open(&sound.path)?;
+	let source = rodio::Decoder::new(BufReader::new(file))?.convert_samples::<f32>();
+	let source = effects::apply_effects(Box::new(source), &sound.effects);
+	buses.play(device_id, Box::new(PlaybackSource::new(source, handle)))?;
 
-	Ok(PlaySound::new(stream, stream_handle, sound))
+	Ok(())
 }
@@ -6,28 +6,281 @@ use anyhow::Result;
 use serde::{ Serialize, Deserialize };
 
 #[serde(rename_all = "camelCase")]
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub enum SoundImage {
 	Url(String),
 	File(String),
+	Cached(String), // Id of an image in the app's image cache, served by the web server
+}
+
+#[serde(tag = "type")]
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum SoundEffect {
+	Speed {
+		factor: f32, // Changes both tempo and pitch
+	},
+	Pitch {
+		semitones: f32,
+	},
+	#[serde(rename_all = "camelCase")]
+	Equalizer {
+		low_gain_db: f32,
+		mid_gain_db: f32,
+		high_gain_db: f32,
+	},
+	#[serde(rename_all = "camelCase")]
+	Echo {
+		delay_ms: u32,
+		feedback: f32,
+		mix: f32,
+	},
+	#[serde(rename_all = "camelCase")]
+	Reverb {
+		room_size: f32,
+		mix: f32,
+	},
+}
+
+impl SoundEffect {
+	// Parameters are clamped when they are read so that the effects can rely on them
+	pub fn clamped(self) -> Self {
+		match self {
+			Self::Speed { factor } => Self::Speed {
+				factor: factor.clamp(0.25, 4.0),
+			},
+			Self::Pitch { semitones } => Self::Pitch {
+				semitones: semitones.clamp(-24.0, 24.0),
+			},
+			Self::Equalizer { low_gain_db, mid_gain_db, high_gain_db } => Self::Equalizer {
+				low_gain_db: low_gain_db.clamp(-24.0, 24.0),
+				mid_gain_db: mid_gain_db.clamp(-24.0, 24.0),
+				high_gain_db: high_gain_db.clamp(-24.0, 24.0),
+			},
+			// Feedback of 1 or more would make the echoes grow forever
+			Self::Echo { delay_ms, feedback, mix } => Self::Echo {
+				delay_ms: delay_ms.clamp(1, 2_000),
+				feedback: feedback.clamp(0.0, 0.95),
+				mix: mix.clamp(0.0, 1.0),
+			},
+			Self::Reverb { room_size, mix } => Self::Reverb {
+				room_size: room_size.clamp(0.0, 1.0),
+				mix: mix.clamp(0.0, 1.0),
+			},
+		}
+	}
+}
+
+fn deserialize_effects<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<SoundEffect>, D::Error> {
+	let effects: Vec<SoundEffect> = Deserialize::deserialize(deserializer)?;
+	Ok(effects.into_iter().map(SoundEffect::clamped).collect())
+}
+
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Speech {
+	pub text: String,
+	pub voice: String,
+	pub rate: u32, // Words per minute
+	pub pitch: u32, // From 0 to 99
+}
+
+impl Default for Speech {
+	fn default() -> Self {
+		Self {
+			text: String::new(),
+			voice: "en".to_owned(),
+			rate: 175,
+			pitch: 50,
+		}
+	}
 }
 
 #[serde(default)]
 #[serde(rename_all = "camelCase")]
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct Sound {
+	pub id: String, // Generated when the sound is added, unlike the path it never changes
 	pub name: String,
 	pub path: String,
 	pub volume: f32,
 	pub image: Option<SoundImage>,
+	pub output_devices: Option<Vec<DeviceId>>, // Output devices to play on, all of them if None
+	#[serde(deserialize_with = "deserialize_effects")]
+	pub effects: Vec<SoundEffect>, // Applied in order
+	pub tts: Option<Speech>, // Set for text-to-speech sounds, the path then points to the rendered speech
+	pub folder: Option<String>, // Path of the folder board the sound is synced with
+	pub hash: Option<String>, // Content hash of the file, used to follow renames
+	pub tags: Vec<String>,
+	pub favourite: bool,
+	pub cooldown_ms: Option<u64>, // Minimum time between two plays of the sound
+}
+
+impl Sound {
+	pub fn new(name: String, path: String) -> Self {
+		Self {
+			id: uuid::Uuid::new_v4().to_string(),
+			name,
+			path,
+			volume: 1.0,
+			..Self::default()
+		}
+	}
+
+	pub fn plays_on_device(&self, device: &DeviceId) -> bool {
+		match &self.output_devices {
+			Some(devices) => devices.iter().any(|id| id.matches(device)),
+			None => true,
+		}
+	}
+}
+
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct SoundGroup {
+	pub name: String,
+	pub sounds: Vec<String>, // Ids of the sounds in this group
+	pub avoid_repeats: bool, // Play every sound once before any of them can be picked again
+}
+
+#[serde(from = "DeviceIdRepr")]
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DeviceId {
+	pub host: String,
+	pub name: String,
+	pub index: usize, // Tells apart devices that have the same name on the same host
+}
+
+// Devices used to be referenced by their name only
+#[serde(untagged)]
+#[derive(Deserialize)]
+enum DeviceIdRepr {
+	Name(String),
+	#[serde(rename_all = "camelCase")]
+	Id {
+		#[serde(default)]
+		host: String,
+		name: String,
+		#[serde(default)]
+		index: usize,
+	},
+}
+
+impl From<DeviceIdRepr> for DeviceId {
+	fn from(repr: DeviceIdRepr) -> Self {
+		match repr {
+			DeviceIdRepr::Name(name) => Self { name, ..Self::default() },
+			DeviceIdRepr::Id { host, name, index } => Self { host, name, index },
+		}
+	}
+}
+
+impl DeviceId {
+	pub fn matches(&self, other: &DeviceId) -> bool {
+		// An empty host comes from a name-only configuration that has not been migrated yet
+		let same_host = self.host.is_empty() || other.host.is_empty() || self.host == other.host;
+		same_host && self.name == other.name && self.index == other.index
+	}
+
+	fn resolve_host(host: &mut String, name: &str, index: usize, available: &[DeviceId]) -> bool {
+		if !host.is_empty() {
+			return false;
+		}
+		match available.iter().find(|dev| dev.name == name && dev.index == index) {
+			Some(dev) => {
+				*host = dev.host.clone();
+				true
+			},
+			None => false,
+		}
+	}
+}
+
+impl std::fmt::Display for DeviceId {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.name)?;
+		if self.index > 0 {
+			write!(f, " #{}", self.index + 1)?;
+		}
+		if !self.host.is_empty() {
+			write!(f, " ({})", self.host)?;
+		}
+		Ok(())
+	}
 }
 
 #[serde(default)]
 #[serde(rename_all = "camelCase")]
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct OutputDevice {
+	pub host: String,
 	pub name: String,
+	pub index: usize,
 	pub volume: f32,
+	#[serde(skip)]
+	pub missing: bool, // Set at runtime when the device is not connected, clients check the available devices themselves
+}
+
+impl OutputDevice {
+	pub fn id(&self) -> DeviceId {
+		DeviceId {
+			host: self.host.clone(),
+			name: self.name.clone(),
+			index: self.index,
+		}
+	}
+}
+
+#[serde(tag = "type")]
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum ScheduleRule {
+	#[serde(rename_all = "camelCase")]
+	Interval {
+		every_secs: u64, // Counted from when the schedule is added or the app starts
+	},
+	Cron {
+		expression: String, // Minute, hour, day of month, month and day of week, in local time
+	},
+}
+
+// Plays a sound automatically, e.g. a reminder every 30 minutes
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Schedule {
+	pub id: String,
+	pub name: String,
+	pub sound: String, // Id of the sound
+	pub rule: ScheduleRule,
+	pub enabled: bool,
+}
+
+impl Default for Schedule {
+	fn default() -> Self {
+		Self {
+			id: String::new(),
+			name: String::new(),
+			sound: String::new(),
+			rule: ScheduleRule::Interval {
+				every_secs: 30 * 60,
+			},
+			enabled: true,
+		}
+	}
+}
+
+// Board bound to a directory, its sounds are added, renamed and removed along with the files
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FolderBoard {
+	pub name: String,
+	pub path: String,
+	pub ignored: Vec<String>, // Files whose sound was deleted by the user, they are not added back
 }
 
 #[serde(default)]
@@ -37,14 +290,152 @@ pub struct SoundsConfig {
 	pub copy_file: bool,
 }
 
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RecordingConfig {
+	pub max_duration_secs: u64, // Recordings are stopped automatically after this long
+}
+
+impl Default for RecordingConfig {
+	fn default() -> Self {
+		Self {
+			max_duration_secs: 120,
+		}
+	}
+}
+
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReplayConfig {
+	pub enabled: bool,
+	pub device: Option<DeviceId>, // Default input device if not set
+	pub loopback: bool, // Capture what is played on `device`, which is then an output device
+	pub duration_secs: u32,
+}
+
+impl Default for ReplayConfig {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			device: None,
+			loopback: false,
+			duration_secs: 30,
+		}
+	}
+}
+
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RateLimitConfig {
+	pub enabled: bool,
+	pub max_plays: u32, // Plays allowed across all sounds within the window
+	pub window_ms: u64,
+}
+
+impl Default for RateLimitConfig {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			max_plays: 5,
+			window_ms: 10_000,
+		}
+	}
+}
+
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TtsConfig {
+	pub command: String,
+}
+
+impl Default for TtsConfig {
+	fn default() -> Self {
+		Self {
+			command: "espeak-ng".to_owned(),
+		}
+	}
+}
+
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LimiterConfig {
+	pub enabled: bool,
+	pub threshold_db: f32,
+}
+
+impl Default for LimiterConfig {
+	fn default() -> Self {
+		// Off by default so that upgrading does not change how existing setups sound
+		Self {
+			enabled: false,
+			threshold_db: -1.0,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum PluginFormat {
+	Vst2,
+}
+
+// Not derived, `#[default]` on enum variants needs Rust 1.62
+#[allow(clippy::derivable_impls)]
+impl Default for PluginFormat {
+	fn default() -> Self {
+		Self::Vst2
+	}
+}
+
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct PluginConfig {
+	pub path: String,
+	pub format: PluginFormat,
+	pub bypass: bool,
+	pub parameters: Vec<f32>, // Normalized values, restored when the plugin is loaded
+	pub chunk: Option<String>, // Base64 state of plugins that save more than their parameters, restored after them
+}
+
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct PluginChainConfig {
+	pub device: DeviceId,
+	pub plugins: Vec<PluginConfig>, // Processed in order, before the limiter
+}
+
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct PluginsConfig {
+	pub folders: Vec<String>,
+}
+
 #[serde(default)]
 #[serde(rename_all = "camelCase")]
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
 	pub global_volume: f32,
 	pub output_devices: Vec<OutputDevice>,
+	pub cue_output_device: Option<OutputDevice>, // Private device used to preview sounds
+	pub limiter: LimiterConfig,
+	pub plugins_config: PluginsConfig,
+	pub plugin_chains: Vec<PluginChainConfig>,
 	pub sounds_config: SoundsConfig,
+	pub tts_config: TtsConfig,
+	pub recording_config: RecordingConfig,
+	pub replay: ReplayConfig,
+	pub rate_limit: RateLimitConfig,
 	pub sounds: Vec<Sound>,
+	pub sound_groups: Vec<SoundGroup>,
+	pub folder_boards: Vec<FolderBoard>,
+	pub schedules: Vec<Schedule>,
 }
 
 impl Default for Config {
@@ -53,7 +444,18 @@ impl Default for Config {
 			sounds: Vec::new(),
 			global_volume: 1.0,
 			sounds_config: SoundsConfig::default(),
+			tts_config: TtsConfig::default(),
+			recording_config: RecordingConfig::default(),
+			replay: ReplayConfig::default(),
+			rate_limit: RateLimitConfig::default(),
 			output_devices: Vec::new(),
+			cue_output_device: None,
+			limiter: LimiterConfig::default(),
+			plugins_config: PluginsConfig::default(),
+			plugin_chains: Vec::new(),
+			sound_groups: Vec::new(),
+			folder_boards: Vec::new(),
+			schedules: Vec::new(),
 		}
 	}
 }
@@ -75,7 +477,12 @@ impl Config {
 
 	pub fn read() -> Self {
 		match Self::read_from_file() {
-			Ok(cfg) => cfg,
+			Ok(mut cfg) => {
+				if cfg.assign_sound_ids() | cfg.migrate_group_references() | cfg.assign_schedule_ids() {
+					cfg.save();
+				}
+				cfg
+			},
 			Err(err) => {
 				log::warn!("Could not read configuration from file (falling back to default configuration): {}", err);
 				let cfg = Self::default();
@@ -97,9 +504,178 @@ impl Config {
 		Ok(())
 	}
 
+	// Fill in the host of devices that were saved with their name only, returns true if anything changed
+	pub fn migrate_device_ids(&mut self, available: &[DeviceId]) -> bool {
+		let mut changed = false;
+
+		for dev in self.output_devices.iter_mut().chain(self.cue_output_device.as_mut()) {
+			changed |= DeviceId::resolve_host(&mut dev.host, &dev.name, dev.index, available);
+		}
+		for id in self.sounds.iter_mut().filter_map(|sound| sound.output_devices.as_mut()).flatten() {
+			changed |= DeviceId::resolve_host(&mut id.host, &id.name, id.index, available);
+		}
+		for id in self.plugin_chains.iter_mut().map(|chain| &mut chain.device) {
+			changed |= DeviceId::resolve_host(&mut id.host, &id.name, id.index, available);
+		}
+
+		changed
+	}
+
+	// Sounds used to be identified by their path, which is still accepted for older clients
+	pub fn sound_index(&self, id: &str) -> Option<usize> {
+		self.sounds
+			.iter()
+			.position(|sound| sound.id == id)
+			.or_else(|| self.sounds.iter().position(|sound| sound.path == id))
+	}
+
+	pub fn find_sound(&self, id: &str) -> Option<&Sound> {
+		self.sound_index(id).map(|idx| &self.sounds[idx])
+	}
+
+	// Gives an id to the sounds that were added before ids existed, returns true if any sound changed
+	fn assign_sound_ids(&mut self) -> bool {
+		let mut ids = std::collections::HashSet::new();
+		let mut changed = false;
+		for sound in self.sounds.iter_mut() {
+			if sound.id.is_empty() || !ids.insert(sound.id.clone()) {
+				sound.id = uuid::Uuid::new_v4().to_string();
+				ids.insert(sound.id.clone());
+				changed = true;
+			}
+		}
+		changed
+	}
+
+	// Schedules written by hand in the configuration file have no id
+	fn assign_schedule_ids(&mut self) -> bool {
+		let mut ids = std::collections::HashSet::new();
+		let mut changed = false;
+		for schedule in self.schedules.iter_mut() {
+			if schedule.id.is_empty() || !ids.insert(schedule.id.clone()) {
+				schedule.id = uuid::Uuid::new_v4().to_string();
+				ids.insert(schedule.id.clone());
+				changed = true;
+			}
+		}
+		changed
+	}
+
+	// Groups used to refer to sounds by path, entries of sounds that no longer exist are dropped.
+	// Returns true if any group changed
+	fn migrate_group_references(&mut self) -> bool {
+		let mut changed = false;
+		for idx in 0..self.sound_groups.len() {
+			let sounds: Vec<String> = self.sound_groups[idx].sounds
+				.iter()
+				.filter_map(|sound| self.find_sound(sound).map(|sound| sound.id.clone()))
+				.collect();
+			if sounds != self.sound_groups[idx].sounds {
+				self.sound_groups[idx].sounds = sounds;
+				changed = true;
+			}
+		}
+		changed
+	}
+
+	// Remove a deleted sound from the groups, returns true if any group changed
+	pub fn remove_sound_references(&mut self, sound_id: &str) -> bool {
+		let mut changed = false;
+		for group in self.sound_groups.iter_mut() {
+			let len = group.sounds.len();
+			group.sounds.retain(|id| id != sound_id);
+			changed |= group.sounds.len() != len;
+		}
+		changed
+	}
+
+	// Schedules cannot do anything without their sound, so they go with it
+	pub fn remove_sound_schedules(&mut self, sound_id: &str) -> bool {
+		let len = self.schedules.len();
+		self.schedules.retain(|schedule| schedule.sound != sound_id);
+		self.schedules.len() != len
+	}
+
 	pub fn save(&self) {
 		if let Err(err) = self.save_to_file() {
 			log::error!("Could not save configuration file: {}", err);
 		}
 	}
 }
+
+#[test]
+fn test_sound_ids_are_assigned_and_looked_up() {
+	let json = r#"{ "sounds": [
+		{ "name": "a", "path": "a.mp3" },
+		{ "id": "x", "name": "b", "path": "b.mp3" },
+		{ "id": "x", "name": "c", "path": "c.mp3" }
+	] }"#;
+	let mut config: Config = serde_json::from_str(json).unwrap();
+	assert!(config.assign_sound_ids());
+	assert!(!config.assign_sound_ids());
+
+	let ids: std::collections::HashSet<&str> = config.sounds.iter().map(|sound| sound.id.as_str()).collect();
+	assert_eq!(ids.len(), 3);
+	assert_eq!(config.sounds[1].id, "x");
+	assert_eq!(config.find_sound("x").unwrap().name, "b");
+	// Paths still work for clients that do not know about ids
+	assert_eq!(config.find_sound("c.mp3").unwrap().name, "c");
+	assert!(config.find_sound("d.mp3").is_none());
+}
+
+#[test]
+fn test_group_paths_are_migrated_to_ids() {
+	let json = r#"{
+		"sounds": [
+			{ "id": "a", "name": "a", "path": "same.mp3" },
+			{ "id": "b", "name": "b", "path": "same.mp3" },
+			{ "id": "c", "name": "c", "path": "c.mp3" }
+		],
+		"soundGroups": [{ "name": "group", "sounds": ["same.mp3", "b", "c.mp3", "gone.mp3"] }]
+	}"#;
+	let mut config: Config = serde_json::from_str(json).unwrap();
+	assert!(config.migrate_group_references());
+	assert_eq!(config.sound_groups[0].sounds, vec!["a", "b", "c"]);
+	assert!(!config.migrate_group_references());
+
+	assert!(config.remove_sound_references("b"));
+	assert_eq!(config.sound_groups[0].sounds, vec!["a", "c"]);
+}
+
+#[test]
+fn test_sound_effects_are_clamped() {
+	let sound: Sound = serde_json::from_str(r#"{ "name": "a", "path": "a.mp3", "effects": [
+		{ "type": "echo", "delayMs": 0, "feedback": 1.5, "mix": -1 },
+		{ "type": "equalizer", "lowGainDb": -100, "midGainDb": 3, "highGainDb": 100 },
+		{ "type": "speed", "factor": 0 }
+	] }"#).unwrap();
+	assert_eq!(sound.effects, vec![
+		SoundEffect::Echo { delay_ms: 1, feedback: 0.95, mix: 0.0 },
+		SoundEffect::Equalizer { low_gain_db: -24.0, mid_gain_db: 3.0, high_gain_db: 24.0 },
+		SoundEffect::Speed { factor: 0.25 },
+	]);
+}
+
+#[test]
+fn test_name_only_devices_are_migrated() {
+	let json = r#"{
+		"outputDevices": [{ "name": "Speakers", "volume": 0.5 }, { "name": "Unplugged", "volume": 1.0 }],
+		"sounds": [{ "id": "a", "name": "a", "path": "a.mp3", "outputDevices": ["Speakers", { "name": "Headset", "index": 1 }] }],
+		"pluginChains": [{ "device": "Speakers", "plugins": [] }]
+	}"#;
+	let mut config: Config = serde_json::from_str(json).unwrap();
+	let speakers = DeviceId { host: "WASAPI".to_owned(), name: "Speakers".to_owned(), index: 0 };
+	let headset = DeviceId { host: "ASIO".to_owned(), name: "Headset".to_owned(), index: 1 };
+	assert_eq!(config.sounds[0].output_devices, Some(vec![
+		DeviceId { name: "Speakers".to_owned(), ..DeviceId::default() },
+		DeviceId { name: "Headset".to_owned(), index: 1, ..DeviceId::default() },
+	]));
+	assert!(config.sounds[0].plays_on_device(&speakers));
+
+	assert!(config.migrate_device_ids(&[speakers.clone(), headset.clone()]));
+	assert_eq!(config.output_devices[0].id(), speakers);
+	assert_eq!(config.output_devices[1].host, "");
+	assert_eq!(config.sounds[0].output_devices, Some(vec![speakers.clone(), headset]));
+	assert_eq!(config.plugin_chains[0].device, speakers);
+	assert!(!config.migrate_device_ids(&[speakers]));
+}
@@ -0,0 +1,155 @@
+use std::{
+	thread,
+	time::Duration,
+	sync::{
+		Arc,
+		atomic::{ AtomicBool, Ordering },
+	},
+};
+
+use crate::audio;
+use crate::config::{ DeviceId, OutputDevice };
+
+pub fn flag_missing_devices<'a>(devices: impl Iterator<Item = &'a mut OutputDevice>, available: &[DeviceId]) {
+	for dev in devices {
+		let id = dev.id();
+		let missing = !available.iter().any(|available| id.matches(available));
+		if missing && !dev.missing {
+			log::warn!("Output device {} is missing", id);
+		}
+		dev.missing = missing;
+	}
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct DeviceChanges {
+	pub added: Vec<DeviceId>,
+	pub removed: Vec<DeviceId>,
+}
+
+impl DeviceChanges {
+	pub fn diff(previous: &[DeviceId], current: &[DeviceId]) -> Self {
+		Self {
+			added: current.iter().filter(|id| !previous.contains(id)).cloned().collect(),
+			removed: previous.iter().filter(|id| !current.contains(id)).cloned().collect(),
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.removed.is_empty()
+	}
+}
+
+#[derive(Default)]
+pub struct DeviceMonitor {
+	stop: Arc<AtomicBool>,
+	thread_handle: Option<thread::JoinHandle<()>>,
+}
+
+impl DeviceMonitor {
+	fn poll_interval() -> Duration {
+		Duration::from_secs(3)
+	}
+
+	pub fn start(&mut self, app: Arc<crate::App>) {
+		let stop = self.stop.clone();
+
+		self.thread_handle = Some(thread::spawn(move || {
+			let mut known: Option<Vec<DeviceId>> = None;
+
+			while !stop.load(Ordering::SeqCst) {
+				match audio::get_output_device_ids() {
+					Ok(current) => {
+						match &known {
+							Some(previous) => {
+								let changes = DeviceChanges::diff(previous, &current);
+								if !changes.is_empty() {
+									Self::on_devices_changed(&app, &current, &changes);
+								}
+							},
+							None => Self::update_missing_devices(&app, &current),
+						}
+						known = Some(current);
+					},
+					Err(err) => log::warn!("Could not enumerate output devices: {}", err),
+				}
+
+				let mut waited = Duration::from_millis(0);
+				while waited < Self::poll_interval() && !stop.load(Ordering::SeqCst) {
+					thread::sleep(Duration::from_millis(100));
+					waited += Duration::from_millis(100);
+				}
+			}
+		}));
+	}
+
+	pub fn stop(&mut self) {
+		log::info!("Stopping device monitor...");
+
+		self.stop.store(true, Ordering::SeqCst);
+		if let Some(handle) = self.thread_handle.take() {
+			let _ = handle.join();
+		}
+	}
+
+	fn update_missing_devices(app: &crate::App, available: &[DeviceId]) -> Vec<OutputDevice> {
+		let mut config = app.config.lock().unwrap();
+		if config.migrate_device_ids(available) {
+			config.save();
+		}
+		let config = &mut *config;
+		flag_missing_devices(config.output_devices.iter_mut().chain(config.cue_output_device.as_mut()), available);
+
+		config.output_devices.clone()
+	}
+
+	fn on_devices_changed(app: &crate::App, current: &[DeviceId], changes: &DeviceChanges) {
+		for name in changes.added.iter() {
+			log::info!("Output device connected: {}", name);
+		}
+		for name in changes.removed.iter() {
+			log::info!("Output device disconnected: {}", name);
+			// The stream is opened again the next time a sound is played on the device
+			app.output_buses.close(name);
+		}
+
+		// The config lock must be released before locking the WebSocket server
+		let config_devices = Self::update_missing_devices(app, current);
+		app.websocket_server
+			.lock().unwrap()
+			.send_output_devices_changed(current, changes, &config_devices);
+	}
+}
+
+#[cfg(test)]
+fn device(name: &str, index: usize) -> DeviceId {
+	DeviceId {
+		host: "WASAPI".to_owned(),
+		name: name.to_owned(),
+		index,
+	}
+}
+
+#[test]
+fn test_device_changes_diff() {
+	let previous = vec![device("Speakers", 0), device("Headset", 0), device("Headset", 1)];
+	let current = vec![device("Headset", 0), device("Speakers", 0), device("Cable", 0)];
+
+	assert_eq!(DeviceChanges::diff(&previous, &current), DeviceChanges {
+		added: vec![device("Cable", 0)],
+		removed: vec![device("Headset", 1)],
+	});
+	assert!(DeviceChanges::diff(&current, &current).is_empty());
+}
+
+#[test]
+fn test_missing_devices_are_flagged_but_not_saved() {
+	let mut devices = [
+		OutputDevice { host: "WASAPI".to_owned(), name: "Speakers".to_owned(), ..OutputDevice::default() },
+		OutputDevice { name: "Headset".to_owned(), ..OutputDevice::default() },
+		OutputDevice { name: "Cable".to_owned(), ..OutputDevice::default() },
+	];
+	flag_missing_devices(devices.iter_mut(), &[device("Speakers", 0), device("Headset", 0)]);
+	assert_eq!(devices.iter().map(|dev| dev.missing).collect::<Vec<bool>>(), vec![false, false, true]);
+	assert!(serde_json::to_value(&devices[2]).unwrap().get("missing").is_none());
+}
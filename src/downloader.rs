@@ -0,0 +1,208 @@
+use std::{
+	fs::File,
+	io::{ Read, Write },
+	path::{ Path, PathBuf },
+	time::Duration,
+};
+
+use anyhow::Result;
+
+pub fn max_download_size() -> u64 {
+	50 * 1024 * 1024 // Bytes
+}
+
+fn extension_for_content_type(content_type: &str) -> Option<&'static str> {
+	match content_type {
+		"audio/mpeg" | "audio/mp3" => Some("mp3"),
+		"audio/wav" | "audio/wave" | "audio/x-wav" | "audio/vnd.wave" => Some("wav"),
+		"audio/ogg" | "audio/vorbis" | "application/ogg" => Some("ogg"),
+		"audio/flac" | "audio/x-flac" => Some("flac"),
+		_ => None,
+	}
+}
+
+fn percent_decode(s: &str) -> String {
+	let bytes = s.as_bytes();
+	let mut out = Vec::with_capacity(bytes.len());
+	let mut i = 0;
+	while i < bytes.len() {
+		let hex = s.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+		match (bytes[i], hex) {
+			(b'%', Some(byte)) => {
+				out.push(byte);
+				i += 3;
+			},
+			(byte, _) => {
+				out.push(byte);
+				i += 1;
+			},
+		}
+	}
+	String::from_utf8_lossy(&out).into_owned()
+}
+
+// Name of the file at the end of the URL path, without the query string
+fn file_name_from_url(url: &str) -> Option<String> {
+	let path = url.split(['?', '#']).next()?;
+	let path = path.splitn(2, "://").last()?;
+	let name = percent_decode(path.split('/').skip(1).last()?);
+	let name: String = name
+		.chars()
+		.map(|c| if c.is_alphanumeric() || " .-_".contains(c) { c } else { '_' })
+		.collect();
+	if name.trim_matches(|c| c == '.' || c == '_' || c == ' ').is_empty() {
+		None
+	} else {
+		Some(name)
+	}
+}
+
+// Servers often send audio files as generic binary data, so the file extension is checked as well
+fn file_extension(content_type: &str, file_name: Option<&str>) -> Result<&'static str> {
+	let url_extension = file_name
+		.and_then(|name| Path::new(name).extension())
+		.map(|ext| ext.to_string_lossy().to_lowercase())
+		.and_then(|ext| crate::audio::supported_extensions().iter().find(|known| **known == ext).copied());
+
+	if let Some(extension) = extension_for_content_type(content_type) {
+		return Ok(url_extension.unwrap_or(extension));
+	}
+	match (content_type, url_extension) {
+		("application/octet-stream", Some(extension)) => Ok(extension),
+		_ => Err(anyhow::Error::msg(format!("Unsupported content type {}", content_type))),
+	}
+}
+
+// Downloads an audio file into `dir`, `on_progress` receives the downloaded and total sizes in bytes
+pub fn download_sound(url: &str, dir: &Path, max_size: u64, mut on_progress: impl FnMut(u64, Option<u64>)) -> Result<PathBuf> {
+	if !url.starts_with("http://") && !url.starts_with("https://") {
+		anyhow::bail!("Only HTTP and HTTPS URLs are supported");
+	}
+
+	// No overall timeout, large files can take a while, but a stalled server does not hold the thread forever
+	let agent = ureq::AgentBuilder::new()
+		.timeout_connect(Duration::from_secs(10))
+		.timeout_read(Duration::from_secs(30))
+		.build();
+	let resp = agent.get(url).call()?;
+	let file_name = file_name_from_url(url);
+	let extension = file_extension(resp.content_type(), file_name.as_deref())?;
+	let total = resp.header("Content-Length").and_then(|len| len.parse::<u64>().ok());
+	if let Some(total) = total {
+		if total > max_size {
+			anyhow::bail!("The file is too large ({} bytes, the limit is {} bytes)", total, max_size);
+		}
+	}
+
+	let stem = file_name
+		.as_deref()
+		.and_then(|name| Path::new(name).file_stem())
+		.map(|stem| stem.to_string_lossy().into_owned())
+		.unwrap_or_else(|| "download".to_owned());
+	let path = crate::utils::unique_path(dir, &stem, extension);
+	let part_path = path.with_extension(format!("{}.part", extension));
+
+	let download = || -> Result<()> {
+		let mut file = File::create(&part_path)?;
+		let mut reader = resp.into_reader();
+		let mut buf = vec![0; 64 * 1024];
+		let mut downloaded = 0;
+		on_progress(downloaded, total);
+		loop {
+			let len = reader.read(&mut buf)?;
+			if len == 0 {
+				break;
+			}
+			downloaded += len as u64;
+			// The Content-Length header can be missing or wrong
+			if downloaded > max_size {
+				anyhow::bail!("The file is too large (the limit is {} bytes)", max_size);
+			}
+			file.write_all(&buf[..len])?;
+			on_progress(downloaded, total);
+		}
+		Ok(())
+	};
+	if let Err(err) = download() {
+		let _ = std::fs::remove_file(&part_path);
+		return Err(err);
+	}
+	std::fs::rename(&part_path, &path)?;
+	log::info!("Downloaded {} to {}", url, path.display());
+
+	Ok(path)
+}
+
+// Serves a single canned HTTP response on a local port and returns the URL to request
+#[cfg(test)]
+fn serve_once(path: &str, content_type: &str, body: Vec<u8>, content_length: bool) -> String {
+	let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+	let url = format!("http://{}{}", listener.local_addr().unwrap(), path);
+	let content_type = content_type.to_owned();
+
+	std::thread::spawn(move || {
+		let (mut stream, _) = listener.accept().unwrap();
+		let mut request = [0; 4096];
+		let _ = stream.read(&mut request);
+
+		let mut head = format!("HTTP/1.1 200 OK\r\nContent-Type: {}\r\nConnection: close\r\n", content_type);
+		if content_length {
+			head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+		}
+		head.push_str("\r\n");
+		let _ = stream.write_all(head.as_bytes());
+		let _ = stream.write_all(&body);
+	});
+
+	url
+}
+
+#[cfg(test)]
+fn test_dir(name: &str) -> PathBuf {
+	let dir = std::env::temp_dir().join(format!("soundboard-download-test-{}-{}", name, std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	dir
+}
+
+#[test]
+fn test_download_sound_saves_file_and_reports_progress() {
+	let dir = test_dir("ok");
+	let body = vec![7; 200_000];
+	let url = serve_once("/sounds/air%20horn.mp3?dl=1", "audio/mpeg", body.clone(), true);
+
+	let mut progress = Vec::new();
+	let path = download_sound(&url, &dir, max_download_size(), |downloaded, total| progress.push((downloaded, total))).unwrap();
+
+	assert_eq!(path, dir.join("air horn.mp3"));
+	assert_eq!(std::fs::read(&path).unwrap(), body);
+	assert_eq!(progress.first(), Some(&(0, Some(200_000))));
+	assert_eq!(progress.last(), Some(&(200_000, Some(200_000))));
+
+	let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_download_sound_rejects_other_content_types() {
+	let dir = test_dir("type");
+	let url = serve_once("/page.mp3", "text/html", b"<html></html>".to_vec(), true);
+
+	assert!(download_sound(&url, &dir, max_download_size(), |_, _| {}).is_err());
+	assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+	let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_download_sound_enforces_size_limit() {
+	let dir = test_dir("size");
+
+	let url = serve_once("/big.wav", "audio/wav", vec![0; 2048], true);
+	assert!(download_sound(&url, &dir, 1024, |_, _| {}).is_err());
+
+	// Without a Content-Length header the limit is checked while downloading
+	let url = serve_once("/big.wav", "application/octet-stream", vec![0; 200_000], false);
+	assert!(download_sound(&url, &dir, 100_000, |_, _| {}).is_err());
+	assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+	let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,485 @@
+use std::time::Duration;
+
+use rodio::Source;
+
+use crate::config::SoundEffect;
+use crate::output_bus::BoxedSource;
+
+// The parameters are already clamped to sensible ranges when the effects are deserialized
+pub fn apply_effects(mut source: BoxedSource, effects: &[SoundEffect]) -> BoxedSource {
+	for effect in effects {
+		let channels = source.channels();
+		let sample_rate = source.sample_rate();
+
+		source = match *effect {
+			SoundEffect::Speed { factor } => Box::new(source.speed(factor)),
+			SoundEffect::Pitch { semitones } => {
+				let processor = PitchShift::new(channels, sample_rate, semitones);
+				Box::new(Processed::new(source, processor, 0))
+			},
+			SoundEffect::Equalizer { low_gain_db, mid_gain_db, high_gain_db } => {
+				let processor = Equalizer::new(channels, sample_rate, low_gain_db, mid_gain_db, high_gain_db);
+				Box::new(Processed::new(source, processor, 0))
+			},
+			SoundEffect::Echo { delay_ms, feedback, mix } => {
+				let processor = Echo::new(channels, sample_rate, delay_ms, feedback, mix);
+				// Let the echoes ring until they are about 60 dB down
+				let repeats = if feedback > 0.0 { (0.001_f32.ln() / feedback.ln()).ceil() as u32 } else { 1 };
+				let tail = Duration::from_millis(delay_ms as u64 * repeats.min(50) as u64);
+				Box::new(Processed::new(source, processor, samples_for(tail, channels, sample_rate)))
+			},
+			SoundEffect::Reverb { room_size, mix } => {
+				let processor = Reverb::new(channels, sample_rate, room_size, mix);
+				let tail = Duration::from_secs_f32(0.5 + 3.0 * room_size);
+				Box::new(Processed::new(source, processor, samples_for(tail, channels, sample_rate)))
+			},
+		};
+	}
+
+	source
+}
+
+fn samples_for(duration: Duration, channels: u16, sample_rate: u32) -> usize {
+	(duration.as_secs_f32() * sample_rate as f32) as usize * channels as usize
+}
+
+pub trait Processor: Send {
+	fn process(&mut self, channel: usize, sample: f32) -> f32;
+}
+
+// Runs every sample of a source through a Processor, feeding it silence after the end of the
+// source for `tail` samples so that delays and reverbs can ring out
+pub struct Processed<S, P> {
+	input: S,
+	processor: P,
+	channels: u16,
+	sample_rate: u32,
+	channel: usize,
+	tail: usize,
+	input_ended: bool,
+}
+
+impl<S, P> Processed<S, P>
+where S: Source<Item = f32>, P: Processor {
+	pub fn new(input: S, processor: P, tail: usize) -> Self {
+		Self {
+			channels: input.channels(),
+			sample_rate: input.sample_rate(),
+			input,
+			processor,
+			channel: 0,
+			tail,
+			input_ended: false,
+		}
+	}
+}
+
+impl<S, P> Iterator for Processed<S, P>
+where S: Source<Item = f32>, P: Processor {
+	type Item = f32;
+
+	fn next(&mut self) -> Option<f32> {
+		let sample = if self.input_ended {
+			None
+		} else {
+			self.input.next()
+		};
+		let sample = match sample {
+			Some(sample) => sample,
+			None => {
+				// Only end on a frame boundary
+				self.input_ended = true;
+				if self.tail == 0 && self.channel == 0 {
+					return None;
+				}
+				self.tail = self.tail.saturating_sub(1);
+				0.0
+			},
+		};
+
+		let out = self.processor.process(self.channel, sample);
+		self.channel = (self.channel + 1) % self.channels.max(1) as usize;
+		Some(out)
+	}
+}
+
+impl<S, P> Source for Processed<S, P>
+where S: Source<Item = f32>, P: Processor {
+	fn current_frame_len(&self) -> Option<usize> {
+		if self.input_ended {
+			Some(self.tail)
+		} else {
+			self.input.current_frame_len()
+		}
+	}
+
+	fn channels(&self) -> u16 {
+		self.channels
+	}
+
+	fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	fn total_duration(&self) -> Option<Duration> {
+		None
+	}
+}
+
+// Delay line pitch shifter: two taps sweep through a short window at a rate that depends
+// on the pitch ratio and are crossfaded so that the jumps back are not heard
+pub struct PitchShift {
+	window: usize,
+	step: f32,
+	channels: Vec<PitchShiftChannel>,
+}
+
+struct PitchShiftChannel {
+	buffer: Vec<f32>,
+	write: usize,
+	phase: f32,
+}
+
+impl PitchShift {
+	fn window_duration() -> f32 {
+		0.05 // Seconds
+	}
+
+	pub fn new(channels: u16, sample_rate: u32, semitones: f32) -> Self {
+		let window = ((sample_rate as f32 * Self::window_duration()) as usize).max(2);
+		let ratio = 2.0_f32.powf(semitones / 12.0);
+
+		Self {
+			window,
+			step: (1.0 - ratio) / window as f32,
+			channels: (0..channels.max(1))
+				.map(|_| PitchShiftChannel {
+					buffer: vec![0.0; window + 1],
+					write: 0,
+					phase: 0.0,
+				})
+				.collect(),
+		}
+	}
+
+	fn read(channel: &PitchShiftChannel, delay: f32) -> f32 {
+		let len = channel.buffer.len() as f32;
+		let pos = (channel.write as f32 - delay).rem_euclid(len);
+		let i = pos.floor() as usize % channel.buffer.len();
+		let j = (i + 1) % channel.buffer.len();
+		let frac = pos - pos.floor();
+		channel.buffer[i] * (1.0 - frac) + channel.buffer[j] * frac
+	}
+}
+
+impl Processor for PitchShift {
+	fn process(&mut self, channel: usize, sample: f32) -> f32 {
+		let window = self.window as f32;
+		let state = &mut self.channels[channel];
+		state.buffer[state.write] = sample;
+
+		let phase_a = state.phase;
+		let phase_b = (state.phase + 0.5).rem_euclid(1.0);
+		// Triangular windows that sum to 1
+		let gain_a = 1.0 - (2.0 * phase_a - 1.0).abs();
+		let gain_b = 1.0 - (2.0 * phase_b - 1.0).abs();
+		let out = Self::read(state, phase_a * window) * gain_a + Self::read(state, phase_b * window) * gain_b;
+
+		state.phase = (state.phase + self.step).rem_euclid(1.0);
+		state.write = (state.write + 1) % state.buffer.len();
+		out
+	}
+}
+
+// Biquad filter, coefficients from the Audio EQ Cookbook
+#[derive(Clone)]
+struct Biquad {
+	b0: f32,
+	b1: f32,
+	b2: f32,
+	a1: f32,
+	a2: f32,
+	x1: f32,
+	x2: f32,
+	y1: f32,
+	y2: f32,
+}
+
+enum FilterKind {
+	LowShelf,
+	Peaking,
+	HighShelf,
+}
+
+impl Biquad {
+	fn new(kind: FilterKind, sample_rate: u32, frequency: f32, gain_db: f32) -> Self {
+		let a = 10.0_f32.powf(gain_db / 40.0);
+		let w0 = 2.0 * std::f32::consts::PI * frequency / sample_rate as f32;
+		let (sin, cos) = w0.sin_cos();
+		let q = std::f32::consts::FRAC_1_SQRT_2;
+		let alpha = sin / (2.0 * q);
+		let sqrt_a = 2.0 * a.sqrt() * alpha;
+
+		let (b0, b1, b2, a0, a1, a2) = match kind {
+			FilterKind::LowShelf => (
+				a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a),
+				2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+				a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a),
+				(a + 1.0) + (a - 1.0) * cos + sqrt_a,
+				-2.0 * ((a - 1.0) + (a + 1.0) * cos),
+				(a + 1.0) + (a - 1.0) * cos - sqrt_a,
+			),
+			FilterKind::Peaking => (
+				1.0 + alpha * a,
+				-2.0 * cos,
+				1.0 - alpha * a,
+				1.0 + alpha / a,
+				-2.0 * cos,
+				1.0 - alpha / a,
+			),
+			FilterKind::HighShelf => (
+				a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a),
+				-2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+				a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a),
+				(a + 1.0) - (a - 1.0) * cos + sqrt_a,
+				2.0 * ((a - 1.0) - (a + 1.0) * cos),
+				(a + 1.0) - (a - 1.0) * cos - sqrt_a,
+			),
+		};
+
+		Self {
+			b0: b0 / a0,
+			b1: b1 / a0,
+			b2: b2 / a0,
+			a1: a1 / a0,
+			a2: a2 / a0,
+			x1: 0.0,
+			x2: 0.0,
+			y1: 0.0,
+			y2: 0.0,
+		}
+	}
+
+	fn process(&mut self, x: f32) -> f32 {
+		let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2 - self.a1 * self.y1 - self.a2 * self.y2;
+		self.x2 = self.x1;
+		self.x1 = x;
+		self.y2 = self.y1;
+		self.y1 = y;
+		y
+	}
+}
+
+// Three band equalizer: low shelf at 200 Hz, peak at 1 kHz, high shelf at 4 kHz
+pub struct Equalizer {
+	channels: Vec<[Biquad; 3]>,
+}
+
+impl Equalizer {
+	pub fn new(channels: u16, sample_rate: u32, low_gain_db: f32, mid_gain_db: f32, high_gain_db: f32) -> Self {
+		let bands = [
+			Biquad::new(FilterKind::LowShelf, sample_rate, 200.0, low_gain_db),
+			Biquad::new(FilterKind::Peaking, sample_rate, 1_000.0, mid_gain_db),
+			Biquad::new(FilterKind::HighShelf, sample_rate, 4_000.0, high_gain_db),
+		];
+
+		Self {
+			channels: (0..channels.max(1)).map(|_| bands.clone()).collect(),
+		}
+	}
+}
+
+impl Processor for Equalizer {
+	fn process(&mut self, channel: usize, sample: f32) -> f32 {
+		self.channels[channel]
+			.iter_mut()
+			.fold(sample, |sample, band| band.process(sample))
+	}
+}
+
+struct DelayLine {
+	buffer: Vec<f32>,
+	position: usize,
+}
+
+impl DelayLine {
+	fn new(length: usize) -> Self {
+		Self {
+			buffer: vec![0.0; length.max(1)],
+			position: 0,
+		}
+	}
+
+	fn read(&self) -> f32 {
+		self.buffer[self.position]
+	}
+
+	fn write_and_advance(&mut self, sample: f32) {
+		self.buffer[self.position] = sample;
+		self.position = (self.position + 1) % self.buffer.len();
+	}
+}
+
+pub struct Echo {
+	feedback: f32,
+	mix: f32,
+	channels: Vec<DelayLine>,
+}
+
+impl Echo {
+	pub fn new(channels: u16, sample_rate: u32, delay_ms: u32, feedback: f32, mix: f32) -> Self {
+		let length = (sample_rate as u64 * delay_ms as u64 / 1_000) as usize;
+
+		Self {
+			feedback,
+			mix,
+			channels: (0..channels.max(1)).map(|_| DelayLine::new(length)).collect(),
+		}
+	}
+}
+
+impl Processor for Echo {
+	fn process(&mut self, channel: usize, sample: f32) -> f32 {
+		let line = &mut self.channels[channel];
+		let delayed = line.read();
+		line.write_and_advance(sample + delayed * self.feedback);
+		sample + delayed * self.mix
+	}
+}
+
+// Schroeder reverb: parallel comb filters followed by allpass filters
+pub struct Reverb {
+	mix: f32,
+	feedback: f32,
+	channels: Vec<ReverbChannel>,
+}
+
+struct ReverbChannel {
+	combs: Vec<DelayLine>,
+	allpasses: Vec<DelayLine>,
+}
+
+impl Reverb {
+	fn comb_delays() -> [f32; 4] {
+		[0.0297, 0.0371, 0.0411, 0.0437] // Seconds
+	}
+
+	fn allpass_delays() -> [f32; 2] {
+		[0.005, 0.0017] // Seconds
+	}
+
+	pub fn new(channels: u16, sample_rate: u32, room_size: f32, mix: f32) -> Self {
+		let samples = |seconds: &f32| (seconds * sample_rate as f32) as usize;
+
+		Self {
+			mix,
+			feedback: 0.7 + 0.28 * room_size,
+			channels: (0..channels.max(1) as usize)
+				.map(|channel| ReverbChannel {
+					// Slightly different delays on each channel widen the stereo image
+					combs: Self::comb_delays().iter().map(|d| DelayLine::new(samples(d) + channel * 23)).collect(),
+					allpasses: Self::allpass_delays().iter().map(|d| DelayLine::new(samples(d))).collect(),
+				})
+				.collect(),
+		}
+	}
+}
+
+impl Processor for Reverb {
+	fn process(&mut self, channel: usize, sample: f32) -> f32 {
+		let feedback = self.feedback;
+		let state = &mut self.channels[channel];
+
+		let mut wet = 0.0;
+		for comb in state.combs.iter_mut() {
+			let delayed = comb.read();
+			comb.write_and_advance(sample + delayed * feedback);
+			wet += delayed;
+		}
+		wet /= state.combs.len() as f32;
+
+		for allpass in state.allpasses.iter_mut() {
+			let delayed = allpass.read();
+			let input = wet + delayed * 0.5;
+			allpass.write_and_advance(input);
+			wet = delayed - input * 0.5;
+		}
+
+		sample * (1.0 - self.mix) + wet * self.mix
+	}
+}
+
+#[cfg(test)]
+fn sine(frequency: f32, sample_rate: u32, seconds: f32) -> BoxedSource {
+	let samples = (0..(sample_rate as f32 * seconds) as usize)
+		.map(|i| (i as f32 * frequency * 2.0 * std::f32::consts::PI / sample_rate as f32).sin() * 0.5)
+		.collect::<Vec<f32>>();
+	Box::new(rodio::buffer::SamplesBuffer::new(1, sample_rate, samples))
+}
+
+#[cfg(test)]
+fn estimate_frequency(samples: &[f32], sample_rate: u32) -> f32 {
+	let crossings = samples
+		.windows(2)
+		.filter(|pair| pair[0] <= 0.0 && pair[1] > 0.0)
+		.count();
+	crossings as f32 * sample_rate as f32 / samples.len() as f32
+}
+
+#[cfg(test)]
+fn rms(samples: &[f32]) -> f32 {
+	(samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+#[test]
+fn test_speed_effect() {
+	let source = apply_effects(sine(440.0, 44_100, 1.0), &[SoundEffect::Speed { factor: 2.0 }]);
+	assert_eq!(source.sample_rate(), 88_200);
+	assert_eq!(source.count(), 44_100);
+}
+
+#[test]
+fn test_pitch_effect() {
+	let source = apply_effects(sine(440.0, 44_100, 2.0), &[SoundEffect::Pitch { semitones: 12.0 }]);
+	let samples: Vec<f32> = source.collect();
+	assert_eq!(samples.len(), 88_200);
+
+	let frequency = estimate_frequency(&samples[44_100..], 44_100);
+	assert!((frequency - 880.0).abs() < 880.0 * 0.05, "estimated {} Hz", frequency);
+}
+
+#[test]
+fn test_equalizer_effect() {
+	let cut_lows = [SoundEffect::Equalizer { low_gain_db: -24.0, mid_gain_db: 0.0, high_gain_db: 0.0 }];
+
+	let low: Vec<f32> = apply_effects(sine(50.0, 44_100, 1.0), &cut_lows).collect();
+	let high: Vec<f32> = apply_effects(sine(8_000.0, 44_100, 1.0), &cut_lows).collect();
+	let reference = rms(&sine(50.0, 44_100, 1.0).collect::<Vec<f32>>());
+
+	assert!(rms(&low[4_410..]) < reference * 0.15);
+	assert!((rms(&high[4_410..]) - reference).abs() < reference * 0.1);
+}
+
+#[test]
+fn test_echo_effect() {
+	let mut samples = vec![0.0; 44_100];
+	samples[0] = 1.0;
+	let impulse: BoxedSource = Box::new(rodio::buffer::SamplesBuffer::new(1, 44_100, samples));
+	let echo = [SoundEffect::Echo { delay_ms: 100, feedback: 0.5, mix: 0.8 }];
+	let out: Vec<f32> = apply_effects(impulse, &echo).collect();
+
+	assert!(out.len() > 44_100); // The tail rings after the end of the input
+	assert_eq!(out[0], 1.0);
+	assert!((out[4_410] - 0.8).abs() < 1e-6);
+	assert!((out[8_820] - 0.4).abs() < 1e-6);
+}
+
+#[test]
+fn test_reverb_effect() {
+	let reverb = [SoundEffect::Reverb { room_size: 0.5, mix: 0.5 }];
+	let out: Vec<f32> = apply_effects(sine(440.0, 44_100, 0.5), &reverb).collect();
+
+	assert!(out.len() > 22_050);
+	assert!(out.iter().all(|s| s.is_finite() && s.abs() < 2.0));
+	assert!(rms(&out[22_050..23_000]) > 0.0);
+}
@@ -0,0 +1,289 @@
+use std::{
+	thread,
+	collections::{ HashMap, HashSet },
+	path::{ Path, PathBuf },
+	time::{ Duration, SystemTime },
+	sync::{
+		Arc,
+		atomic::{ AtomicBool, Ordering },
+	},
+};
+
+use crate::audio;
+use crate::config::Sound;
+
+#[derive(Debug, Clone)]
+pub struct FolderFile {
+	pub path: String,
+	pub hash: String,
+}
+
+#[derive(Debug, Default)]
+pub struct FolderChanges {
+	pub added: Vec<Sound>,
+	pub removed: Vec<Sound>,
+	pub renamed: Vec<(String, Sound)>, // Previous path and renamed sound
+	pub hashes_changed: bool, // Files that were modified in place
+	pub ignored_changed: bool,
+}
+
+impl FolderChanges {
+	pub fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.removed.is_empty() && self.renamed.is_empty()
+	}
+}
+
+fn file_stem(path: &str) -> String {
+	Path::new(path)
+		.file_stem()
+		.map(|stem| stem.to_string_lossy().into_owned())
+		.unwrap_or_default()
+}
+
+// Updates the sounds of a folder board to match the files found in the folder. A sound whose file
+// disappeared follows a new file with the same contents, so that its settings are kept across renames.
+// Files in `ignored` do not get a sound
+pub fn sync_folder(sounds: &mut Vec<Sound>, folder: &str, files: &[FolderFile], ignored: &mut Vec<String>) -> FolderChanges {
+	let mut changes = FolderChanges::default();
+	// Files that are gone, or that a sound uses again after an undo, no longer need to be ignored
+	let ignored_len = ignored.len();
+	ignored.retain(|path| files.iter().any(|file| file.path == *path) && !sounds.iter().any(|sound| sound.path == *path));
+	changes.ignored_changed = ignored.len() != ignored_len;
+
+	let mut unclaimed: Vec<&FolderFile> = files
+		.iter()
+		.filter(|file| !sounds.iter().any(|sound| sound.path == file.path) && !ignored.contains(&file.path))
+		.collect();
+
+	for sound in sounds.iter_mut().filter(|sound| sound.folder.as_deref() == Some(folder)) {
+		if let Some(file) = files.iter().find(|file| file.path == sound.path) {
+			if sound.hash.as_ref() != Some(&file.hash) {
+				sound.hash = Some(file.hash.clone());
+				changes.hashes_changed = true;
+			}
+			continue;
+		}
+
+		let renamed_to = sound.hash
+			.as_ref()
+			.and_then(|hash| unclaimed.iter().position(|file| file.hash == *hash));
+		match renamed_to {
+			Some(idx) => {
+				let file = unclaimed.remove(idx);
+				let previous_path = std::mem::replace(&mut sound.path, file.path.clone());
+				// Only follow the file name if the sound was not renamed by the user
+				if sound.name == file_stem(&previous_path) {
+					sound.name = file_stem(&file.path);
+				}
+				changes.renamed.push((previous_path, sound.clone()));
+			},
+			None => changes.removed.push(sound.clone()),
+		}
+	}
+	sounds.retain(|sound| !changes.removed.iter().any(|removed| removed.id == sound.id));
+
+	for file in unclaimed {
+		let sound = Sound {
+			folder: Some(folder.to_owned()),
+			hash: Some(file.hash.clone()),
+			..Sound::new(file_stem(&file.path), file.path.clone())
+		};
+		changes.added.push(sound.clone());
+		sounds.push(sound);
+	}
+
+	changes
+}
+
+// Lists the audio files of a folder and its subfolders, files are only hashed again when they change
+#[derive(Default)]
+pub struct FolderScanner {
+	cache: HashMap<PathBuf, (u64, Option<SystemTime>, String)>,
+}
+
+impl FolderScanner {
+	pub fn scan(&mut self, dir: &Path) -> std::io::Result<Vec<(PathBuf, String)>> {
+		let mut paths = Vec::new();
+		crate::utils::find_files(dir, &audio::is_supported_file, &mut paths)?;
+
+		let mut files = Vec::new();
+		for path in paths {
+			let metadata = match std::fs::metadata(&path) {
+				Ok(metadata) => metadata,
+				Err(_) => continue,
+			};
+			let modified = metadata.modified().ok();
+			let hash = match self.cache.get(&path) {
+				Some((len, cached_modified, hash)) if *len == metadata.len() && *cached_modified == modified => hash.clone(),
+				_ => match crate::utils::hash_file(&path) {
+					Ok(hash) => {
+						self.cache.insert(path.clone(), (metadata.len(), modified, hash.clone()));
+						hash
+					},
+					Err(err) => {
+						log::warn!("Could not read {}: {}", path.display(), err);
+						continue;
+					},
+				},
+			};
+			files.push((path, hash));
+		}
+
+		// Files that were deleted or moved out of the folder are forgotten
+		self.cache.retain(|path, _| !path.starts_with(dir) || files.iter().any(|(file, _)| file == path));
+
+		Ok(files)
+	}
+
+	// Forgets the files of the folders that are no longer watched
+	pub fn retain_folders(&mut self, dirs: &[&Path]) {
+		self.cache.retain(|path, _| dirs.iter().any(|dir| path.starts_with(dir)));
+	}
+}
+
+#[derive(Default)]
+pub struct FolderWatcher {
+	stop: Arc<AtomicBool>,
+	thread_handle: Option<thread::JoinHandle<()>>,
+}
+
+impl FolderWatcher {
+	fn poll_interval() -> Duration {
+		Duration::from_secs(5)
+	}
+
+	pub fn start(&mut self, app: Arc<crate::App>) {
+		let stop = self.stop.clone();
+
+		self.thread_handle = Some(thread::spawn(move || {
+			let mut scanner = FolderScanner::default();
+			let mut unreachable = HashSet::new();
+
+			while !stop.load(Ordering::SeqCst) {
+				let boards = app.config.lock().unwrap().folder_boards.clone();
+				let app_dir = crate::App::get_working_directory().ok();
+				let mut folders = Vec::new();
+				scanner.retain_folders(&boards.iter().map(|board| Path::new(&board.path)).collect::<Vec<&Path>>());
+
+				for board in boards {
+					match scanner.scan(Path::new(&board.path)) {
+						Ok(files) => {
+							unreachable.remove(&board.path);
+							let files = files
+								.into_iter()
+								.map(|(path, hash)| {
+									let mut path = path.to_string_lossy().into_owned();
+									let _ = crate::ws::WebSocketServer::process_sound_path(&mut path, app_dir.as_ref());
+									FolderFile { path, hash }
+								})
+								.collect();
+							folders.push((board.path, files));
+						},
+						// The sounds are kept while the folder cannot be read, e.g. on a drive that is not plugged in
+						Err(err) => {
+							if unreachable.insert(board.path.clone()) {
+								log::warn!("Could not scan folder board {}: {}", board.path, err);
+							}
+						},
+					}
+				}
+
+				if !folders.is_empty() {
+					app.websocket_server
+						.lock().unwrap()
+						.sync_folder_boards(&folders);
+				}
+
+				let mut waited = Duration::from_millis(0);
+				while waited < Self::poll_interval() && !stop.load(Ordering::SeqCst) {
+					thread::sleep(Duration::from_millis(100));
+					waited += Duration::from_millis(100);
+				}
+			}
+		}));
+	}
+
+	pub fn stop(&mut self) {
+		log::info!("Stopping folder watcher...");
+
+		self.stop.store(true, Ordering::SeqCst);
+		if let Some(handle) = self.thread_handle.take() {
+			let _ = handle.join();
+		}
+	}
+}
+
+#[cfg(test)]
+fn folder_file(path: &str, hash: &str) -> FolderFile {
+	FolderFile {
+		path: path.to_owned(),
+		hash: hash.to_owned(),
+	}
+}
+
+#[test]
+fn test_sync_folder_follows_renames_by_hash() {
+	let mut sounds = Vec::new();
+	let changes = sync_folder(&mut sounds, "clips", &[folder_file("clips/a.mp3", "1"), folder_file("clips/b.mp3", "2")], &mut Vec::new());
+	assert_eq!(changes.added.len(), 2);
+	assert_eq!(sounds.len(), 2);
+
+	sounds[0].volume = 0.5;
+	sounds[1].name = "Custom".to_owned();
+	let changes = sync_folder(&mut sounds, "clips", &[folder_file("clips/c.mp3", "1"), folder_file("clips/d.mp3", "2")], &mut Vec::new());
+	assert!(changes.added.is_empty() && changes.removed.is_empty());
+	assert_eq!(changes.renamed.len(), 2);
+	assert_eq!(changes.renamed[0].0, "clips/a.mp3");
+	assert_eq!((sounds[0].name.as_str(), sounds[0].path.as_str(), sounds[0].volume), ("c", "clips/c.mp3", 0.5));
+	assert_eq!((sounds[1].name.as_str(), sounds[1].path.as_str()), ("Custom", "clips/d.mp3"));
+}
+
+#[test]
+fn test_sync_folder_adds_and_removes_files() {
+	let mut sounds = vec![Sound::new("manual".to_owned(), "other/manual.mp3".to_owned())];
+	sync_folder(&mut sounds, "clips", &[folder_file("clips/a.mp3", "1")], &mut Vec::new());
+
+	let changes = sync_folder(&mut sounds, "clips", &[folder_file("clips/b.mp3", "2")], &mut Vec::new());
+	assert_eq!(changes.removed.len(), 1);
+	assert_eq!(changes.removed[0].path, "clips/a.mp3");
+	assert_eq!(changes.added.len(), 1);
+	// Sounds that do not belong to the folder are left alone
+	let paths: Vec<&str> = sounds.iter().map(|sound| sound.path.as_str()).collect();
+	assert_eq!(paths, vec!["other/manual.mp3", "clips/b.mp3"]);
+}
+
+#[test]
+fn test_sync_folder_skips_ignored_files() {
+	let mut sounds = Vec::new();
+	let mut ignored = vec!["clips/a.mp3".to_owned(), "clips/gone.mp3".to_owned()];
+	let files = [folder_file("clips/a.mp3", "1"), folder_file("clips/b.mp3", "2")];
+
+	let changes = sync_folder(&mut sounds, "clips", &files, &mut ignored);
+	assert_eq!(changes.added.len(), 1);
+	assert_eq!(changes.added[0].path, "clips/b.mp3");
+	// The ignored file that no longer exists is dropped from the list
+	assert!(changes.ignored_changed);
+	assert_eq!(ignored, vec!["clips/a.mp3"]);
+
+	let changes = sync_folder(&mut sounds, "clips", &files, &mut ignored);
+	assert!(changes.is_empty() && !changes.ignored_changed);
+}
+
+#[test]
+fn test_folder_scanner_forgets_deleted_files() {
+	let dir = std::env::temp_dir().join(format!("soundboard-scanner-test-{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	std::fs::write(dir.join("a.wav"), b"a").unwrap();
+	std::fs::write(dir.join("b.wav"), b"b").unwrap();
+
+	let mut scanner = FolderScanner::default();
+	assert_eq!(scanner.scan(&dir).unwrap().len(), 2);
+	std::fs::remove_file(dir.join("a.wav")).unwrap();
+	assert_eq!(scanner.scan(&dir).unwrap().len(), 1);
+	assert_eq!(scanner.cache.keys().collect::<Vec<&PathBuf>>(), vec![&dir.join("b.wav")]);
+
+	scanner.retain_folders(&[]);
+	assert!(scanner.cache.is_empty());
+
+	let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,230 @@
+use std::collections::VecDeque;
+
+use crate::config::{ Config, Sound, SoundGroup, Schedule, OutputDevice };
+
+// The parts of the config that an undoable change can modify
+#[derive(Debug, Clone)]
+pub enum Snapshot {
+	Library {
+		sounds: Vec<Sound>,
+		sound_groups: Vec<SoundGroup>,
+		schedules: Vec<Schedule>, // Deleting a sound also deletes its schedules
+	},
+	OutputDevices(Vec<OutputDevice>),
+}
+
+impl Snapshot {
+	pub fn library(config: &Config) -> Self {
+		Self::Library {
+			sounds: config.sounds.clone(),
+			sound_groups: config.sound_groups.clone(),
+			schedules: config.schedules.clone(),
+		}
+	}
+
+	pub fn output_devices(config: &Config) -> Self {
+		Self::OutputDevices(config.output_devices.clone())
+	}
+
+	// Takes the same parts of the config, so that restoring this snapshot can be reverted
+	fn current(&self, config: &Config) -> Self {
+		match self {
+			Self::Library { .. } => Self::library(config),
+			Self::OutputDevices(_) => Self::output_devices(config),
+		}
+	}
+
+	fn restore(self, config: &mut Config) {
+		match self {
+			Self::Library { sounds, sound_groups, schedules } => {
+				config.sounds = sounds;
+				config.sound_groups = sound_groups;
+				config.schedules = schedules;
+			},
+			Self::OutputDevices(devices) => config.output_devices = devices,
+		}
+	}
+}
+
+struct Entry {
+	action: String,
+	merge_key: Option<String>,
+	snapshot: Snapshot,
+}
+
+// Bounded undo and redo stacks of config snapshots, each taken before a change
+pub struct History {
+	undo: VecDeque<Entry>,
+	redo: Vec<Entry>,
+	capacity: usize,
+}
+
+impl Default for History {
+	fn default() -> Self {
+		Self::with_capacity(50)
+	}
+}
+
+impl History {
+	pub fn with_capacity(capacity: usize) -> Self {
+		Self {
+			undo: VecDeque::new(),
+			redo: Vec::new(),
+			capacity: capacity.max(1),
+		}
+	}
+
+	pub fn record(&mut self, action: &str, before: Snapshot) {
+		self.push(action, None, before);
+	}
+
+	// Consecutive changes with the same action and key are undone together, e.g. the keystrokes editing a sound's name
+	pub fn record_merging(&mut self, action: &str, key: &str, before: Snapshot) {
+		let merges = match self.undo.back() {
+			Some(last) => self.redo.is_empty() && last.action == action && last.merge_key.as_deref() == Some(key),
+			None => false,
+		};
+		if merges {
+			return;
+		}
+		self.push(action, Some(key.to_owned()), before);
+	}
+
+	fn push(&mut self, action: &str, merge_key: Option<String>, before: Snapshot) {
+		self.redo.clear();
+		if self.undo.len() == self.capacity {
+			self.undo.pop_front();
+		}
+		self.undo.push_back(Entry {
+			action: action.to_owned(),
+			merge_key,
+			snapshot: before,
+		});
+	}
+
+	// Restores the config as it was before the last change, returns the state that was replaced
+	pub fn undo(&mut self, config: &mut Config) -> Option<&Snapshot> {
+		let entry = self.undo.pop_back()?;
+		// Changes made after an undo or redo start a new entry
+		self.redo.push(Entry {
+			action: entry.action,
+			merge_key: None,
+			snapshot: entry.snapshot.current(config),
+		});
+		entry.snapshot.restore(config);
+		self.redo.last().map(|entry| &entry.snapshot)
+	}
+
+	pub fn redo(&mut self, config: &mut Config) -> Option<&Snapshot> {
+		let entry = self.redo.pop()?;
+		self.undo.push_back(Entry {
+			action: entry.action,
+			merge_key: None,
+			snapshot: entry.snapshot.current(config),
+		});
+		entry.snapshot.restore(config);
+		self.undo.back().map(|entry| &entry.snapshot)
+	}
+
+	pub fn undo_action(&self) -> Option<&str> {
+		self.undo.back().map(|entry| entry.action.as_str())
+	}
+
+	pub fn redo_action(&self) -> Option<&str> {
+		self.redo.last().map(|entry| entry.action.as_str())
+	}
+
+	// Sounds that can come back with undo or redo, their files must be kept
+	pub fn sounds(&self) -> impl Iterator<Item = &Sound> {
+		self.undo
+			.iter()
+			.chain(self.redo.iter())
+			.filter_map(|entry| match &entry.snapshot {
+				Snapshot::Library { sounds, .. } => Some(sounds),
+				_ => None,
+			})
+			.flatten()
+	}
+}
+
+#[cfg(test)]
+fn sound_paths(config: &Config) -> Vec<&str> {
+	config.sounds.iter().map(|sound| sound.path.as_str()).collect()
+}
+
+#[test]
+fn test_history_undo_redo() {
+	let mut config = Config::default();
+	let mut history = History::with_capacity(2);
+
+	for path in &["a.mp3", "b.mp3", "c.mp3"] {
+		history.record("addSounds", Snapshot::library(&config));
+		config.sounds.push(Sound::new(path.to_string(), path.to_string()));
+	}
+
+	// Only the last two changes are kept
+	assert!(history.undo(&mut config).is_some());
+	assert!(history.undo(&mut config).is_some());
+	assert!(history.undo(&mut config).is_none());
+	assert_eq!(sound_paths(&config), vec!["a.mp3"]);
+
+	assert!(history.redo(&mut config).is_some());
+	assert_eq!(sound_paths(&config), vec!["a.mp3", "b.mp3"]);
+	assert_eq!((history.undo_action(), history.redo_action()), (Some("addSounds"), Some("addSounds")));
+
+	// A new change drops the changes that were undone
+	history.record("deleteSound", Snapshot::library(&config));
+	config.sounds.remove(0);
+	assert!(history.redo_action().is_none());
+	history.undo(&mut config);
+	assert_eq!(sound_paths(&config), vec!["a.mp3", "b.mp3"]);
+	assert_eq!(history.sounds().count(), 2);
+}
+
+#[test]
+fn test_history_merges_edits_of_the_same_sound() {
+	let mut config = Config::default();
+	config.sounds.push(Sound::new("a".to_owned(), "a.mp3".to_owned()));
+	config.sounds.push(Sound::new("b".to_owned(), "b.mp3".to_owned()));
+	let (a, b) = (config.sounds[0].id.clone(), config.sounds[1].id.clone());
+	let mut history = History::default();
+
+	for name in &["a1", "a12", "a123"] {
+		history.record_merging("editSound", &a, Snapshot::library(&config));
+		config.sounds[0].name = name.to_string();
+	}
+	history.record_merging("editSound", &b, Snapshot::library(&config));
+	config.sounds[1].name = "b1".to_owned();
+
+	history.undo(&mut config);
+	assert_eq!((config.sounds[0].name.as_str(), config.sounds[1].name.as_str()), ("a123", "b"));
+	history.undo(&mut config);
+	assert_eq!(config.sounds[0].name, "a");
+	assert!(history.undo_action().is_none());
+
+	// An edit after a redo is not merged into the entry that was redone
+	history.redo(&mut config);
+	history.record_merging("editSound", &a, Snapshot::library(&config));
+	config.sounds[0].name = "a1234".to_owned();
+	history.undo(&mut config);
+	assert_eq!(config.sounds[0].name, "a123");
+}
+
+#[test]
+fn test_history_brings_back_the_schedules_of_deleted_sounds() {
+	let mut config = Config::default();
+	config.sounds.push(Sound::new("a".to_owned(), "a.mp3".to_owned()));
+	config.schedules.push(Schedule {
+		id: "s".to_owned(),
+		sound: config.sounds[0].id.clone(),
+		..Schedule::default()
+	});
+	let mut history = History::default();
+
+	history.record("deleteSound", Snapshot::library(&config));
+	let id = config.sounds.remove(0).id;
+	assert!(config.remove_sound_schedules(&id));
+	history.undo(&mut config);
+	assert_eq!(config.schedules.len(), 1);
+	assert_eq!(config.schedules[0].sound, config.sounds[0].id);
+}
@@ -0,0 +1,127 @@
+use std::{
+	ffi::OsStr,
+	io::Read,
+	path::{ Path, PathBuf },
+	time::Duration,
+};
+
+use anyhow::Result;
+use image::ImageFormat;
+
+use crate::config::SoundImage;
+
+pub fn max_image_size() -> u64 {
+	10 * 1024 * 1024 // Bytes
+}
+
+pub fn thumbnail_size() -> u32 {
+	128 // Pixels
+}
+
+pub fn cache_directory() -> Result<PathBuf> {
+	let mut path = crate::App::get_working_directory()?;
+	path.push("images");
+	std::fs::create_dir_all(path.join("thumbnails"))?;
+	Ok(path)
+}
+
+fn extension(format: ImageFormat) -> Result<&'static str> {
+	match format {
+		ImageFormat::Png => Ok("png"),
+		ImageFormat::Jpeg => Ok("jpg"),
+		ImageFormat::Gif => Ok("gif"),
+		ImageFormat::Bmp => Ok("bmp"),
+		ImageFormat::WebP => Ok("webp"),
+		ImageFormat::Ico => Ok("ico"),
+		_ => Err(anyhow::Error::msg(format!("Unsupported image format {:?}", format))),
+	}
+}
+
+// Images are named after the hash of their contents, which also keeps ids that come from a request inside the cache
+pub fn thumbnail_path(dir: &Path, id: &str) -> Option<PathBuf> {
+	let path = Path::new(id);
+	if path.file_name() != Some(OsStr::new(id)) || !crate::library::is_stored_file(path) {
+		return None;
+	}
+	Some(dir.join("thumbnails").join(path.with_extension("png")))
+}
+
+// Adds an image and its thumbnail to the cache and returns its id
+pub fn store_image(dir: &Path, bytes: &[u8]) -> Result<String> {
+	let format = image::guess_format(bytes)?;
+	let id = format!("{}.{}", crate::utils::hash_bytes(bytes), extension(format)?);
+	let path = dir.join(&id);
+	if !path.exists() {
+		let thumbnail_path = thumbnail_path(dir, &id).ok_or_else(|| anyhow::Error::msg("Invalid image id"))?;
+		let image = image::load_from_memory_with_format(bytes, format)?;
+		image
+			.thumbnail(thumbnail_size(), thumbnail_size())
+			.save_with_format(thumbnail_path, ImageFormat::Png)?;
+		std::fs::write(&path, bytes)?;
+	}
+	Ok(id)
+}
+
+fn download(url: &str) -> Result<Vec<u8>> {
+	let agent = ureq::AgentBuilder::new().timeout(Duration::from_secs(10)).build();
+	let resp = agent.get(url).call()?;
+	let mut bytes = Vec::new();
+	resp.into_reader().take(max_image_size() + 1).read_to_end(&mut bytes)?;
+	if bytes.len() as u64 > max_image_size() {
+		anyhow::bail!("The image is too large (the limit is {} bytes)", max_image_size());
+	}
+	Ok(bytes)
+}
+
+// Replaces an image URL or file by a copy in the cache
+pub fn cache_image(dir: &Path, image: &SoundImage) -> Result<SoundImage> {
+	let bytes = match image {
+		SoundImage::Url(url) => download(url)?,
+		SoundImage::File(path) => std::fs::read(path)?,
+		SoundImage::Cached(_) => return Ok(image.clone()),
+	};
+	Ok(SoundImage::Cached(store_image(dir, &bytes)?))
+}
+
+// Picture embedded in the tags of an MP3 or FLAC file, the front cover if there are several
+pub fn extract_cover_art(path: &Path) -> Option<Vec<u8>> {
+	let extension = path.extension()?.to_string_lossy().to_lowercase();
+	match extension.as_str() {
+		"mp3" => {
+			let tag = id3::Tag::read_from_path(path).ok()?;
+			let picture = tag.pictures()
+				.find(|picture| picture.picture_type == id3::frame::PictureType::CoverFront)
+				.or_else(|| tag.pictures().next())?;
+			Some(picture.data.clone())
+		},
+		"flac" => {
+			let tag = metaflac::Tag::read_from_path(path).ok()?;
+			let picture = tag.pictures()
+				.find(|picture| picture.picture_type == metaflac::block::PictureType::CoverFront)
+				.or_else(|| tag.pictures().next())?;
+			Some(picture.data.clone())
+		},
+		_ => None,
+	}
+}
+
+#[test]
+fn test_store_image_creates_thumbnail() {
+	use image::GenericImageView;
+
+	let dir = std::env::temp_dir().join(format!("soundboard-images-test-{}", std::process::id()));
+	std::fs::create_dir_all(dir.join("thumbnails")).unwrap();
+	let mut bytes = Vec::new();
+	image::DynamicImage::new_rgb8(400, 200).write_to(&mut bytes, ImageFormat::Png).unwrap();
+
+	let id = store_image(&dir, &bytes).unwrap();
+	assert!(id.ends_with(".png"));
+	assert_eq!(std::fs::read(dir.join(&id)).unwrap(), bytes);
+	let thumbnail = image::open(thumbnail_path(&dir, &id).unwrap()).unwrap();
+	assert_eq!(thumbnail.dimensions(), (128, 64));
+
+	assert!(thumbnail_path(&dir, "../config.json").is_none());
+	assert!(thumbnail_path(&dir, &format!("thumbnails/{}", id)).is_none());
+
+	let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,239 @@
+use std::{
+	fs, io,
+	collections::{ HashMap, HashSet },
+	path::{ Path, PathBuf },
+};
+
+use crate::config::{ Sound, SoundImage };
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MissingFiles {
+	pub id: String,
+	pub path: String,
+	pub name: String,
+	pub file_missing: bool,
+	pub image_missing: bool,
+}
+
+fn image_file(sound: &Sound) -> Option<&str> {
+	match &sound.image {
+		Some(SoundImage::File(path)) => Some(path),
+		_ => None,
+	}
+}
+
+// Sounds whose file or image file cannot be found
+pub fn find_missing_files(sounds: &[Sound]) -> Vec<MissingFiles> {
+	sounds
+		.iter()
+		.filter_map(|sound| {
+			let file_missing = !Path::new(&sound.path).exists();
+			let image_missing = match image_file(sound) {
+				Some(path) => !Path::new(path).exists(),
+				None => false,
+			};
+			if file_missing || image_missing {
+				Some(MissingFiles {
+					id: sound.id.clone(),
+					path: sound.path.clone(),
+					name: sound.name.clone(),
+					file_missing,
+					image_missing,
+				})
+			} else {
+				None
+			}
+		})
+		.collect()
+}
+
+pub fn is_image_file(path: &Path) -> bool {
+	match path.extension() {
+		Some(ext) => ["png", "jpg", "jpeg", "gif", "webp", "bmp"].contains(&ext.to_string_lossy().to_lowercase().as_str()),
+		None => false,
+	}
+}
+
+fn file_name(path: &Path) -> Option<String> {
+	path.file_name().map(|name| name.to_string_lossy().to_lowercase())
+}
+
+// Looks for the missing files of a sound among `files`: first by content hash when the sound has one,
+// then by file name as long as only one file has that name
+pub struct Relinker<'a> {
+	files: &'a [PathBuf],
+	hashes: HashMap<&'a Path, Option<String>>,
+	hash_file: &'a dyn Fn(&Path) -> Option<String>,
+}
+
+impl<'a> Relinker<'a> {
+	pub fn new(files: &'a [PathBuf], hash_file: &'a dyn Fn(&Path) -> Option<String>) -> Self {
+		Self {
+			files,
+			hashes: HashMap::new(),
+			hash_file,
+		}
+	}
+
+	fn find_by_name(&self, missing: &str) -> Option<&'a PathBuf> {
+		let name = file_name(Path::new(missing))?;
+		let mut candidates = self.files.iter().filter(|file| file_name(file).as_ref() == Some(&name));
+		match (candidates.next(), candidates.next()) {
+			(Some(file), None) => Some(file),
+			_ => None,
+		}
+	}
+
+	fn find_by_hash(&mut self, hash: &str) -> Option<&'a PathBuf> {
+		let hash_file = self.hash_file;
+		let hashes = &mut self.hashes;
+		self.files
+			.iter()
+			.find(|file| hashes.entry(file.as_path()).or_insert_with(|| hash_file(file)).as_deref() == Some(hash))
+	}
+
+	// Returns the sound with its paths updated, or None if nothing could be relinked
+	pub fn relink(&mut self, sound: &Sound) -> Option<Sound> {
+		let mut relinked = sound.clone();
+		let mut changed = false;
+
+		if !Path::new(&sound.path).exists() {
+			let found = match &sound.hash {
+				Some(hash) => self.find_by_hash(hash).or_else(|| self.find_by_name(&sound.path)),
+				None => self.find_by_name(&sound.path),
+			};
+			if let Some(file) = found {
+				relinked.path = file.to_string_lossy().into_owned();
+				changed = true;
+			}
+		}
+		if let Some(image) = image_file(sound).filter(|path| !Path::new(path).exists()) {
+			if let Some(file) = self.find_by_name(image) {
+				relinked.image = Some(SoundImage::File(file.to_string_lossy().into_owned()));
+				changed = true;
+			}
+		}
+
+		if changed {
+			Some(relinked)
+		} else {
+			None
+		}
+	}
+}
+
+// Files copied into the sounds directory are named after the hash of their contents, so that two
+// different files with the same name cannot overwrite each other
+pub fn is_stored_file(path: &Path) -> bool {
+	match path.file_stem() {
+		Some(stem) => stem.len() == 64 && stem.to_string_lossy().chars().all(|c| c.is_ascii_hexdigit()),
+		None => false,
+	}
+}
+
+// Copies a file into the sounds directory, or moves it if it is already in there
+pub fn store_file(sounds_dir: &Path, path: &Path, hash: &str) -> io::Result<PathBuf> {
+	let mut target = sounds_dir.join(hash);
+	if let Some(extension) = path.extension() {
+		target.set_extension(extension.to_string_lossy().to_lowercase());
+	}
+	if target == path {
+		return Ok(target);
+	}
+
+	let in_sounds_dir = path.parent() == Some(sounds_dir);
+	if target.exists() {
+		// Same contents, the copy is not needed
+		if in_sounds_dir {
+			fs::remove_file(path)?;
+		}
+	} else if in_sounds_dir {
+		fs::rename(path, &target)?;
+	} else {
+		fs::copy(path, &target)?;
+	}
+
+	Ok(target)
+}
+
+// Removes the stored files that are not referenced anymore, other files in the directory are left alone
+pub fn remove_orphaned_files(sounds_dir: &Path, referenced: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+	let referenced: HashSet<PathBuf> = referenced
+		.iter()
+		.filter_map(|path| path.canonicalize().ok())
+		.collect();
+
+	let mut removed = Vec::new();
+	for entry in fs::read_dir(sounds_dir)? {
+		let path = entry?.path();
+		if !path.is_file() || !is_stored_file(&path) || referenced.contains(&path.canonicalize()?) {
+			continue;
+		}
+		fs::remove_file(&path)?;
+		removed.push(path);
+	}
+
+	Ok(removed)
+}
+
+#[test]
+fn test_relink_by_hash_then_unique_name() {
+	let files = vec![
+		PathBuf::from("/new/renamed.mp3"),
+		PathBuf::from("/new/b.mp3"),
+		PathBuf::from("/new/dup/c.mp3"),
+		PathBuf::from("/new/c.mp3"),
+		PathBuf::from("/new/cover.png"),
+	];
+	let hash_file = |path: &Path| if path.ends_with("renamed.mp3") { Some("abc".to_owned()) } else { None };
+	let mut relinker = Relinker::new(&files, &hash_file);
+
+	let by_hash = Sound {
+		hash: Some("abc".to_owned()),
+		..Sound::new("a".to_owned(), "/missing/a.mp3".to_owned())
+	};
+	assert_eq!(relinker.relink(&by_hash).unwrap().path, "/new/renamed.mp3");
+
+	let by_name = Sound {
+		image: Some(SoundImage::File("/missing/cover.png".to_owned())),
+		..Sound::new("b".to_owned(), "/missing/B.mp3".to_owned())
+	};
+	let relinked = relinker.relink(&by_name).unwrap();
+	assert_eq!(relinked.path, "/new/b.mp3");
+	assert!(matches!(relinked.image, Some(SoundImage::File(path)) if path == "/new/cover.png"));
+
+	// Two files share the name, so neither is picked
+	let ambiguous = Sound::new("c".to_owned(), "/missing/c.mp3".to_owned());
+	assert!(relinker.relink(&ambiguous).is_none());
+}
+
+#[test]
+fn test_stored_files_are_shared_and_collected() {
+	let dir = std::env::temp_dir().join(format!("soundboard-library-test-{}", std::process::id()));
+	let sounds_dir = dir.join("sounds");
+	fs::create_dir_all(&sounds_dir).unwrap();
+	let hash = "ab".repeat(32);
+
+	let source = dir.join("Horn.MP3");
+	fs::write(&source, b"horn").unwrap();
+	let stored = store_file(&sounds_dir, &source, &hash).unwrap();
+	assert_eq!(stored, sounds_dir.join(format!("{}.mp3", hash)));
+	assert!(is_stored_file(&stored) && source.exists());
+
+	// A recording that is already in the sounds directory is moved, or dropped if it is a duplicate
+	let recording = sounds_dir.join("recording.wav");
+	fs::write(&recording, b"horn").unwrap();
+	let mp3 = sounds_dir.join("horn.mp3");
+	fs::write(&mp3, b"horn").unwrap();
+	assert_eq!(store_file(&sounds_dir, &mp3, &hash).unwrap(), stored);
+	assert!(!mp3.exists());
+
+	let removed = remove_orphaned_files(&sounds_dir, std::slice::from_ref(&stored)).unwrap();
+	assert!(removed.is_empty());
+	let removed = remove_orphaned_files(&sounds_dir, &[]).unwrap();
+	assert_eq!(removed, vec![stored]);
+	assert!(recording.exists());
+
+	let _ = fs::remove_dir_all(&dir);
+}
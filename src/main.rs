@@ -10,12 +10,31 @@ use anyhow::Result;
 use argparse::{ ArgumentParser, StoreTrue };
 
 pub mod ws;
+pub mod api;
+pub mod tts;
 pub mod audio;
+pub mod stats;
 pub mod utils;
 pub mod config;
+pub mod replay;
+pub mod search;
+pub mod effects;
+pub mod history;
+pub mod images;
+pub mod library;
+pub mod plugins;
 pub mod updater;
+pub mod recorder;
 pub mod autostart;
+pub mod scheduler;
+pub mod shortcut;
+pub mod rate_limit;
 pub mod web_server;
+pub mod downloader;
+pub mod output_bus;
+pub mod folder_sync;
+pub mod sound_groups;
+pub mod device_monitor;
 pub mod window_manager;
 pub mod settings_window;
 pub mod soundboard_window;
@@ -25,8 +44,14 @@ use config::Config;
 pub struct App {
 	pub args: Args,
 	pub config: Mutex<Config>,
+	pub stats: Mutex<stats::PlayStats>,
+	pub output_buses: output_bus::OutputBuses,
 	pub update_available: Option<String>,
 	pub web_server: Arc<Mutex<web_server::WebServer>>,
+	pub device_monitor: Arc<Mutex<device_monitor::DeviceMonitor>>,
+	pub replay: Arc<Mutex<replay::InstantReplay>>,
+	pub folder_watcher: Arc<Mutex<folder_sync::FolderWatcher>>,
+	pub scheduler: Arc<Mutex<scheduler::Scheduler>>,
 	pub websocket_server: Arc<Mutex<ws::WebSocketServer>>,
 	pub window_manager: Arc<Mutex<window_manager::WindowManager>>,
 }
@@ -151,11 +176,24 @@ async fn main() -> Result<()> {
 	log::info!("Version {}", env!("CARGO_PKG_VERSION"));
 	log::info!("PID: {}", std::process::id());
 
+	let config = Config::read();
+	let mut stats = stats::PlayStats::read();
+	stats.retain_sounds(|id| config.find_sound(id).is_some());
+	let output_buses = output_bus::OutputBuses::default();
+	output_buses.set_limiter(&config.limiter);
+	output_buses.set_plugin_chains(&config.plugin_chains);
+
 	let mut app = App {
 		args: Args::parse(),
 		update_available: None,
-		config: Mutex::new(Config::read()),
+		config: Mutex::new(config),
+		stats: Mutex::new(stats),
+		output_buses,
 		web_server: Arc::new(Mutex::new(web_server::WebServer::default())),
+		device_monitor: Arc::new(Mutex::new(device_monitor::DeviceMonitor::default())),
+		replay: Arc::new(Mutex::new(replay::InstantReplay::default())),
+		folder_watcher: Arc::new(Mutex::new(folder_sync::FolderWatcher::default())),
+		scheduler: Arc::new(Mutex::new(scheduler::Scheduler::default())),
 		websocket_server: Arc::new(Mutex::new(ws::WebSocketServer::default())),
 		window_manager: Arc::new(Mutex::new(window_manager::WindowManager::default())),
 	};
@@ -174,13 +212,40 @@ async fn main() -> Result<()> {
 		std::thread::sleep(std::time::Duration::from_millis(100));
 	}
 
-	// Start serving web assets
-	app.web_server.lock().unwrap().start()?;
+	// Move the images of older sounds into the image cache
+	app.websocket_server.lock().unwrap().cache_sound_images();
+
+	// Render the speech that was removed from the cache
+	app.websocket_server.lock().unwrap().render_missing_speech();
+
+	// Watch for output devices being plugged in or out
+	app.device_monitor.lock().unwrap().start(app.clone());
+
+	// Keep folder boards in sync with their directories
+	app.folder_watcher.lock().unwrap().start(app.clone());
+
+	// Play scheduled sounds
+	app.scheduler.lock().unwrap().start(app.clone());
+
+	// Keep the last seconds of the replay device in memory
+	let replay_config = app.config.lock().unwrap().replay.clone();
+	let res = app.replay.lock().unwrap().start(&replay_config);
+	match res {
+		Ok(()) => app.websocket_server.lock().unwrap().update_replay_shortcut(&replay_config),
+		Err(err) => log::error!("Could not start instant replay: {}", err),
+	}
+
+	// Start serving web assets and the REST API
+	app.web_server.lock().unwrap().start(app.clone())?;
 
 	// Create windows
 	let win_mngr = app.window_manager.clone();
 	window_manager::WindowManager::run(win_mngr, app.clone())?;
 
+	app.device_monitor.lock().unwrap().stop();
+	app.replay.lock().unwrap().stop();
+	app.folder_watcher.lock().unwrap().stop();
+	app.scheduler.lock().unwrap().stop();
 	app.websocket_server.lock().unwrap().stop();
 	app.web_server.lock().unwrap().stop().await;
 
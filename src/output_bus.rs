@@ -0,0 +1,538 @@
+use std::{
+	thread,
+	time::Duration,
+	sync::{
+		Arc, Mutex, mpsc,
+		atomic::{ AtomicBool, AtomicU32, Ordering },
+	},
+};
+
+use anyhow::Result;
+use rodio::Source;
+use rodio::source::UniformSourceIterator;
+use cpal::traits::DeviceTrait;
+
+use crate::audio;
+use crate::plugins::{ self, PluginChain, PluginHandle };
+use crate::config::{ DeviceId, LimiterConfig, PluginChainConfig, PluginConfig };
+
+pub type BoxedSource = Box<dyn Source<Item = f32> + Send>;
+
+// Frames mixed at once, plugins process a whole block per call
+fn block_size() -> usize {
+	256
+}
+
+pub fn db_to_gain(db: f32) -> f32 {
+	10.0_f32.powf(db / 20.0)
+}
+
+// Soft-knee compressor with a high ratio. The peak envelope falls back slowly and the gain reduction
+// follows it with a short attack, so transients are rounded off instead of being flattened in a single sample
+pub struct Limiter {
+	envelope: f32,
+	reduction_db: f32, // Positive
+	attack_coeff: f32,
+	release_coeff: f32,
+}
+
+impl Limiter {
+	fn attack_duration() -> f32 {
+		0.005 // Seconds
+	}
+
+	fn release_duration() -> f32 {
+		0.15 // Seconds
+	}
+
+	fn ratio() -> f32 {
+		20.0
+	}
+
+	fn knee_db() -> f32 {
+		6.0
+	}
+
+	pub fn new(sample_rate: u32) -> Self {
+		let coeff = |duration: f32| (-1.0 / (sample_rate as f32 * duration)).exp();
+		Self {
+			envelope: 0.0,
+			reduction_db: 0.0,
+			attack_coeff: coeff(Self::attack_duration()),
+			release_coeff: coeff(Self::release_duration()),
+		}
+	}
+
+	pub fn gain(&self) -> f32 {
+		db_to_gain(-self.reduction_db)
+	}
+
+	// Gain reduction wanted for a level, both in dB
+	fn target_reduction(level_db: f32, threshold_db: f32) -> f32 {
+		let slope = 1.0 - 1.0 / Self::ratio();
+		let over = level_db - threshold_db;
+		let half_knee = Self::knee_db() / 2.0;
+		if over <= -half_knee {
+			0.0
+		} else if over >= half_knee {
+			slope * over
+		} else {
+			slope * (over + half_knee).powi(2) / (2.0 * Self::knee_db())
+		}
+	}
+
+	pub fn process_frame(&mut self, frame: &mut [f32], threshold_db: f32) {
+		let peak = frame.iter().fold(0.0_f32, |max, sample| max.max(sample.abs()));
+		self.envelope = peak.max(self.envelope * self.release_coeff);
+		let target = if self.envelope > 0.0 { Self::target_reduction(20.0 * self.envelope.log10(), threshold_db) } else { 0.0 };
+
+		if target > self.reduction_db {
+			self.reduction_db = target + (self.reduction_db - target) * self.attack_coeff;
+		} else {
+			self.reduction_db = target;
+		}
+
+		let gain = self.gain();
+		for sample in frame.iter_mut() {
+			*sample *= gain;
+		}
+	}
+}
+
+// Levels since the last time they were read
+pub struct BusMeter {
+	peak: AtomicU32,
+	output_peak: AtomicU32,
+	min_gain: AtomicU32,
+	sum_squares: AtomicU32,
+	samples: AtomicU32,
+}
+
+impl Default for BusMeter {
+	fn default() -> Self {
+		Self {
+			peak: AtomicU32::new(0.0_f32.to_bits()),
+			output_peak: AtomicU32::new(0.0_f32.to_bits()),
+			min_gain: AtomicU32::new(1.0_f32.to_bits()),
+			sum_squares: AtomicU32::new(0.0_f32.to_bits()),
+			samples: AtomicU32::new(0),
+		}
+	}
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BusLevels {
+	pub device: DeviceId,
+	pub peak: f32, // Before the limiter
+	pub output_peak: f32,
+	pub rms: f32, // After the limiter
+	pub gain_reduction: f32, // Lowest gain applied by the limiter, 1.0 if it did not kick in
+}
+
+impl BusMeter {
+	fn update(atomic: &AtomicU32, f: impl Fn(f32) -> f32) {
+		let _ = atomic.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| Some(f(f32::from_bits(bits)).to_bits()));
+	}
+
+	fn record(&self, peak: f32, output: &[f32], min_gain: f32) {
+		let output_peak = output.iter().fold(0.0_f32, |max, sample| max.max(sample.abs()));
+		let sum_squares: f32 = output.iter().map(|sample| sample * sample).sum();
+
+		Self::update(&self.peak, |prev| prev.max(peak));
+		Self::update(&self.output_peak, |prev| prev.max(output_peak));
+		Self::update(&self.min_gain, |prev| prev.min(min_gain));
+		Self::update(&self.sum_squares, |prev| prev + sum_squares);
+		self.samples.fetch_add(output.len() as u32, Ordering::Relaxed);
+	}
+
+	fn take(&self, device: DeviceId) -> BusLevels {
+		let sum_squares = f32::from_bits(self.sum_squares.swap(0.0_f32.to_bits(), Ordering::Relaxed));
+		let samples = self.samples.swap(0, Ordering::Relaxed);
+
+		BusLevels {
+			device,
+			peak: f32::from_bits(self.peak.swap(0.0_f32.to_bits(), Ordering::Relaxed)),
+			output_peak: f32::from_bits(self.output_peak.swap(0.0_f32.to_bits(), Ordering::Relaxed)),
+			rms: if samples == 0 { 0.0 } else { (sum_squares / samples as f32).sqrt() },
+			gain_reduction: f32::from_bits(self.min_gain.swap(1.0_f32.to_bits(), Ordering::Relaxed)),
+		}
+	}
+}
+
+struct BusShared {
+	pending: Mutex<Vec<BoxedSource>>,
+	has_pending: AtomicBool,
+	stop: AtomicBool,
+	limiter_enabled: AtomicBool,
+	threshold_db: AtomicU32,
+	meter: BusMeter,
+	pending_chain: Mutex<Option<PluginChain>>,
+	has_pending_chain: AtomicBool,
+	retired_chains: Mutex<Vec<PluginChain>>, // Replaced on the audio thread, dropped on the next change
+	plugin_handles: Mutex<Vec<PluginHandle>>,
+}
+
+impl BusShared {
+	fn new(limiter: &LimiterConfig) -> Self {
+		let shared = Self {
+			pending: Mutex::new(Vec::new()),
+			has_pending: AtomicBool::new(false),
+			stop: AtomicBool::new(false),
+			limiter_enabled: AtomicBool::new(false),
+			threshold_db: AtomicU32::new(0.0_f32.to_bits()),
+			meter: BusMeter::default(),
+			pending_chain: Mutex::new(None),
+			has_pending_chain: AtomicBool::new(false),
+			retired_chains: Mutex::new(Vec::new()),
+			plugin_handles: Mutex::new(Vec::new()),
+		};
+		shared.set_limiter(limiter);
+		shared
+	}
+
+	fn set_limiter(&self, limiter: &LimiterConfig) {
+		self.limiter_enabled.store(limiter.enabled, Ordering::Relaxed);
+		self.threshold_db.store(limiter.threshold_db.to_bits(), Ordering::Relaxed);
+	}
+
+	fn set_chain(&self, chain: PluginChain) {
+		self.retired_chains.lock().unwrap().clear();
+		*self.plugin_handles.lock().unwrap() = chain.iter().map(|plugin| plugin.handle()).collect();
+		*self.pending_chain.lock().unwrap() = Some(chain);
+		self.has_pending_chain.store(true, Ordering::SeqCst);
+	}
+}
+
+// Mixes every sound played on a device, then runs the sum through the plugin chain and the limiter
+struct BusSource {
+	channels: u16,
+	sample_rate: u32,
+	shared: Arc<BusShared>,
+	sources: Vec<UniformSourceIterator<BoxedSource, f32>>,
+	plugins: PluginChain,
+	limiter: Limiter,
+	block: Vec<f32>,
+	position: usize,
+}
+
+impl BusSource {
+	fn new(channels: u16, sample_rate: u32, shared: Arc<BusShared>, plugins: PluginChain) -> Self {
+		let block = vec![0.0; channels as usize * block_size()];
+		Self {
+			channels,
+			sample_rate,
+			shared,
+			sources: Vec::new(),
+			plugins,
+			limiter: Limiter::new(sample_rate),
+			position: block.len(),
+			block,
+		}
+	}
+
+	fn render_block(&mut self) {
+		if self.shared.has_pending.swap(false, Ordering::SeqCst) {
+			let mut pending = self.shared.pending.lock().unwrap();
+			for source in pending.drain(..) {
+				self.sources.push(UniformSourceIterator::new(source, self.channels, self.sample_rate));
+			}
+		}
+		if self.shared.has_pending_chain.swap(false, Ordering::SeqCst) {
+			if let Some(chain) = self.shared.pending_chain.lock().unwrap().take() {
+				let previous = std::mem::replace(&mut self.plugins, chain);
+				self.shared.retired_chains.lock().unwrap().push(previous);
+			}
+		}
+
+		for sample in self.block.iter_mut() {
+			*sample = 0.0;
+		}
+		let mut finished = Vec::new();
+		for (idx, source) in self.sources.iter_mut().enumerate() {
+			for sample in self.block.iter_mut() {
+				match source.next() {
+					Some(value) => *sample += value,
+					None => {
+						finished.push(idx);
+						break;
+					},
+				}
+			}
+		}
+		for idx in finished.into_iter().rev() {
+			self.sources.remove(idx);
+		}
+
+		let channels = self.channels as usize;
+		for plugin in self.plugins.iter_mut() {
+			plugin.process(&mut self.block, channels);
+		}
+
+		let peak = self.block.iter().fold(0.0_f32, |max, sample| max.max(sample.abs()));
+		let mut min_gain = self.limiter.gain();
+		if self.shared.limiter_enabled.load(Ordering::Relaxed) {
+			let threshold_db = f32::from_bits(self.shared.threshold_db.load(Ordering::Relaxed));
+			for frame in self.block.chunks_mut(channels) {
+				self.limiter.process_frame(frame, threshold_db);
+				min_gain = min_gain.min(self.limiter.gain());
+			}
+		}
+		self.shared.meter.record(peak, &self.block, min_gain);
+
+		self.position = 0;
+	}
+}
+
+impl Iterator for BusSource {
+	type Item = f32;
+
+	fn next(&mut self) -> Option<f32> {
+		if self.shared.stop.load(Ordering::Relaxed) {
+			return None;
+		}
+		if self.position >= self.block.len() {
+			self.render_block();
+		}
+
+		let sample = self.block[self.position];
+		self.position += 1;
+		Some(sample)
+	}
+}
+
+impl Source for BusSource {
+	fn current_frame_len(&self) -> Option<usize> {
+		None
+	}
+
+	fn channels(&self) -> u16 {
+		self.channels
+	}
+
+	fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	fn total_duration(&self) -> Option<Duration> {
+		None
+	}
+}
+
+pub struct OutputBus {
+	pub device: DeviceId,
+	sample_rate: u32,
+	shared: Arc<BusShared>,
+}
+
+impl OutputBus {
+	pub fn open(device_id: &DeviceId, limiter: &LimiterConfig, plugins: &[PluginConfig]) -> Result<Self> {
+		let shared = Arc::new(BusShared::new(limiter));
+		let (tx, rx) = mpsc::channel();
+
+		// rodio streams cannot be moved across threads, so each bus keeps its stream on its own thread
+		let thread_shared = shared.clone();
+		let thread_device_id = device_id.clone();
+		let plugin_configs = plugins.to_vec();
+		thread::spawn(move || {
+			let open = || -> Result<(rodio::OutputStream, u32)> {
+				let devices = audio::get_output_devices()?;
+				let device = devices
+					.iter()
+					.find(|dev| thread_device_id.matches(&dev.id))
+					.ok_or_else(|| anyhow::Error::msg(format!("Device {} not found", thread_device_id)))?;
+				let config = device.device.default_output_config()?;
+				let (stream, stream_handle) = rodio::OutputStream::try_from_device(&device.device)?;
+				let chain = plugins::load_chain(&plugin_configs, config.sample_rate().0, block_size());
+				*thread_shared.plugin_handles.lock().unwrap() = chain.iter().map(|plugin| plugin.handle()).collect();
+				let source = BusSource::new(config.channels(), config.sample_rate().0, thread_shared.clone(), chain);
+				stream_handle
+					.play_raw(source)
+					.map_err(|err| anyhow::Error::msg(format!("{:?}", err)))?;
+				Ok((stream, config.sample_rate().0))
+			};
+
+			match open() {
+				Ok((_stream, sample_rate)) => {
+					let _ = tx.send(Ok(sample_rate));
+					while !thread_shared.stop.load(Ordering::SeqCst) {
+						thread::sleep(Duration::from_millis(100));
+					}
+				},
+				Err(err) => {
+					let _ = tx.send(Err(err));
+				},
+			}
+		});
+
+		let sample_rate = rx.recv()??;
+		log::info!("Opened output device {}", device_id);
+
+		Ok(Self {
+			device: device_id.clone(),
+			sample_rate,
+			shared,
+		})
+	}
+
+	pub fn play(&self, source: BoxedSource) {
+		self.shared.pending.lock().unwrap().push(source);
+		self.shared.has_pending.store(true, Ordering::SeqCst);
+	}
+
+	pub fn set_plugins(&self, plugins: &[PluginConfig]) {
+		self.shared.set_chain(plugins::load_chain(plugins, self.sample_rate, block_size()));
+	}
+
+	pub fn plugin(&self, slot: usize) -> Option<PluginHandle> {
+		self.shared.plugin_handles.lock().unwrap().iter().find(|plugin| plugin.slot == slot).cloned()
+	}
+}
+
+impl Drop for OutputBus {
+	fn drop(&mut self) {
+		self.shared.stop.store(true, Ordering::SeqCst);
+	}
+}
+
+// Output buses are opened the first time a sound is played on a device and kept open afterwards
+#[derive(Default)]
+pub struct OutputBuses {
+	buses: Mutex<Vec<OutputBus>>,
+	limiter: Mutex<LimiterConfig>,
+	plugin_chains: Mutex<Vec<PluginChainConfig>>,
+	last_levels: Mutex<Vec<BusLevels>>,
+}
+
+impl OutputBuses {
+	// Opening a device and loading its plugins can be slow, so the other buses stay usable in the meantime
+	fn with_bus<T>(&self, device_id: &DeviceId, f: impl FnOnce(&OutputBus) -> T) -> Result<T> {
+		if let Some(bus) = self.buses.lock().unwrap().iter().find(|bus| bus.device.matches(device_id)) {
+			return Ok(f(bus));
+		}
+
+		let plugins = self.plugin_chains
+			.lock().unwrap()
+			.iter()
+			.find(|chain| chain.device.matches(device_id))
+			.map(|chain| chain.plugins.clone())
+			.unwrap_or_default();
+		let limiter = self.limiter.lock().unwrap().clone();
+		let bus = OutputBus::open(device_id, &limiter, &plugins)?;
+
+		let mut buses = self.buses.lock().unwrap();
+		// The device may have been opened by another thread in the meantime, the new bus is then closed
+		if let Some(existing) = buses.iter().find(|bus| bus.device.matches(device_id)) {
+			return Ok(f(existing));
+		}
+		// The limiter may have changed while the bus was opening
+		bus.shared.set_limiter(&self.limiter.lock().unwrap());
+		let result = f(&bus);
+		buses.push(bus);
+
+		Ok(result)
+	}
+
+	pub fn play(&self, device_id: &DeviceId, source: BoxedSource) -> Result<()> {
+		self.with_bus(device_id, |bus| bus.play(source))
+	}
+
+	pub fn close(&self, device_id: &DeviceId) {
+		self.buses.lock().unwrap().retain(|bus| !bus.device.matches(device_id));
+	}
+
+	pub fn set_limiter(&self, limiter: &LimiterConfig) {
+		*self.limiter.lock().unwrap() = limiter.clone();
+		for bus in self.buses.lock().unwrap().iter() {
+			bus.shared.set_limiter(limiter);
+		}
+	}
+
+	pub fn set_plugin_chains(&self, chains: &[PluginChainConfig]) {
+		*self.plugin_chains.lock().unwrap() = chains.to_vec();
+	}
+
+	// Reloads the plugins of the device's bus if it is open, otherwise they are loaded when it opens
+	pub fn set_plugins(&self, device_id: &DeviceId, plugins: &[PluginConfig]) {
+		{
+			let mut chains = self.plugin_chains.lock().unwrap();
+			chains.retain(|chain| !chain.device.matches(device_id));
+			chains.push(PluginChainConfig {
+				device: device_id.clone(),
+				plugins: plugins.to_vec(),
+			});
+		}
+
+		if let Some(bus) = self.buses.lock().unwrap().iter().find(|bus| bus.device.matches(device_id)) {
+			bus.set_plugins(plugins);
+		}
+	}
+
+	// Opens the device's bus if needed so that the plugin is loaded
+	pub fn plugin(&self, device_id: &DeviceId, slot: usize) -> Result<PluginHandle> {
+		self.with_bus(device_id, |bus| bus.plugin(slot))?
+			.ok_or_else(|| anyhow::Error::msg(format!("No plugin loaded in slot {} of device {}", slot, device_id)))
+	}
+
+	// Reads and resets the meters, called periodically whether anyone listens or not so that they never pile up
+	pub fn update_levels(&self) -> Vec<BusLevels> {
+		let levels: Vec<BusLevels> = self.buses
+			.lock().unwrap()
+			.iter()
+			.map(|bus| bus.shared.meter.take(bus.device.clone()))
+			.collect();
+		*self.last_levels.lock().unwrap() = levels.clone();
+		levels
+	}
+
+	// Levels from the last update, without resetting the meters
+	pub fn levels(&self) -> Vec<BusLevels> {
+		self.last_levels.lock().unwrap().clone()
+	}
+}
+
+#[test]
+fn test_limiter_compresses_smoothly() {
+	let threshold_db = -1.0;
+	let mut limiter = Limiter::new(48_000);
+
+	let mut previous_gain = limiter.gain();
+	for i in 0..48_000 {
+		let value = 2.5 * (i as f32 * 440.0 * 2.0 * std::f32::consts::PI / 48_000.0).sin();
+		let mut frame = [value, -value];
+		limiter.process_frame(&mut frame, threshold_db);
+		// No sudden gain jumps, even on the first peak
+		assert!((limiter.gain() - previous_gain).abs() < 0.02);
+		previous_gain = limiter.gain();
+		// Once the attack is over the output stays close to the threshold
+		if i > 4_800 {
+			assert!(frame.iter().all(|sample| sample.abs() <= db_to_gain(threshold_db + 1.0)));
+		}
+	}
+	assert!(limiter.gain() < 0.5);
+
+	// Quiet signals under the knee are left alone and the gain recovers
+	for _ in 0..48_000 {
+		limiter.process_frame(&mut [0.1, 0.1], threshold_db);
+	}
+	assert!(limiter.gain() > 0.99);
+}
+
+#[test]
+fn test_bus_meter_resets_when_read() {
+	let meter = BusMeter::default();
+	let device = DeviceId::default();
+	assert_eq!(meter.take(device.clone()).gain_reduction, 1.0);
+
+	meter.record(2.0, &[0.5, -0.5], 0.5);
+	let levels = meter.take(device.clone());
+	assert_eq!(levels.peak, 2.0);
+	assert_eq!(levels.output_peak, 0.5);
+	assert_eq!(levels.rms, 0.5);
+	assert_eq!(levels.gain_reduction, 0.5);
+
+	let levels = meter.take(device);
+	assert_eq!(levels.peak, 0.0);
+	assert_eq!(levels.rms, 0.0);
+	assert_eq!(levels.gain_reduction, 1.0);
+}
@@ -0,0 +1,245 @@
+use std::{
+	ffi::OsStr,
+	path::{ Path, PathBuf },
+	sync::{ Arc, Mutex },
+};
+
+use anyhow::Result;
+use vst::host::{ Host, HostBuffer, PluginLoader };
+use vst::plugin::{ Plugin, PluginParameters };
+
+use crate::config::{ PluginConfig, PluginFormat };
+
+struct PluginHost;
+
+impl Host for PluginHost {
+	fn automate(&self, index: i32, value: f32) {
+		log::debug!("Plugin parameter {} automated to {}", index, value);
+	}
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PluginInfo {
+	pub path: String,
+	pub format: PluginFormat,
+	pub name: String,
+	pub vendor: String,
+	pub parameters: i32,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PluginParameter {
+	pub index: i32,
+	pub name: String,
+	pub value: f32,
+	pub text: String,
+	pub label: String,
+}
+
+fn load_vst(path: &Path) -> Result<vst::host::PluginInstance> {
+	let host = Arc::new(Mutex::new(PluginHost));
+	let mut loader = PluginLoader::load(path, host)
+		.map_err(|err| anyhow::Error::msg(format!("Could not load plugin {}: {}", path.display(), err)))?;
+	let instance = loader
+		.instance()
+		.map_err(|err| anyhow::Error::msg(format!("Could not instantiate plugin {}: {}", path.display(), err)))?;
+	Ok(instance)
+}
+
+fn find_plugin_files(dir: &Path, files: &mut Vec<PathBuf>) {
+	let entries = match std::fs::read_dir(dir) {
+		Ok(entries) => entries,
+		Err(err) => {
+			log::warn!("Could not read plugin folder {}: {}", dir.display(), err);
+			return;
+		},
+	};
+
+	for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+		if path.is_dir() {
+			find_plugin_files(&path, files);
+		} else if path.extension() == Some(OsStr::new(std::env::consts::DLL_EXTENSION)) {
+			files.push(path);
+		}
+	}
+}
+
+// Loads every plugin found in the folders to read its info, files that are not plugins are skipped
+pub fn scan(folders: &[String]) -> Vec<PluginInfo> {
+	let mut files = Vec::new();
+	for folder in folders {
+		find_plugin_files(Path::new(folder), &mut files);
+	}
+
+	files
+		.iter()
+		.filter_map(|path| match load_vst(path) {
+			Ok(instance) => {
+				let info = instance.get_info();
+				Some(PluginInfo {
+					path: path.to_string_lossy().into_owned(),
+					format: PluginFormat::Vst2,
+					name: info.name,
+					vendor: info.vendor,
+					parameters: info.parameters,
+				})
+			},
+			Err(err) => {
+				log::debug!("{}", err);
+				None
+			},
+		})
+		.collect()
+}
+
+// Parameters can be read and changed from any thread while the plugin runs on the audio thread
+#[derive(Clone)]
+pub struct PluginHandle {
+	pub slot: usize, // Position of the plugin in the chain config
+	parameter_count: i32,
+	preset_chunks: bool,
+	parameters: Arc<dyn PluginParameters>,
+}
+
+// The parameter object is meant to be used by the host from other threads than the audio one
+unsafe impl Send for PluginHandle {}
+unsafe impl Sync for PluginHandle {}
+
+impl PluginHandle {
+	pub fn parameters(&self) -> Vec<PluginParameter> {
+		(0..self.parameter_count)
+			.map(|index| PluginParameter {
+				index,
+				name: self.parameters.get_parameter_name(index),
+				value: self.parameters.get_parameter(index),
+				text: self.parameters.get_parameter_text(index),
+				label: self.parameters.get_parameter_label(index),
+			})
+			.collect()
+	}
+
+	pub fn set_parameter(&self, index: i32, value: f32) -> Result<()> {
+		if index < 0 || index >= self.parameter_count {
+			anyhow::bail!("Plugin parameter {} does not exist", index);
+		}
+		self.parameters.set_parameter(index, value.clamp(0.0, 1.0));
+		Ok(())
+	}
+
+	pub fn values(&self) -> Vec<f32> {
+		(0..self.parameter_count).map(|index| self.parameters.get_parameter(index)).collect()
+	}
+
+	// Full state of the current preset, for plugins that keep state outside of their parameters
+	pub fn chunk(&self) -> Option<String> {
+		if self.preset_chunks {
+			Some(base64::encode(self.parameters.get_preset_data()))
+		} else {
+			None
+		}
+	}
+}
+
+pub struct LoadedPlugin {
+	instance: vst::host::PluginInstance,
+	buffer: HostBuffer<f32>,
+	inputs: Vec<Vec<f32>>,
+	outputs: Vec<Vec<f32>>,
+	handle: PluginHandle,
+}
+
+// The instance is only used by one thread at a time: it is loaded on the caller's thread, then moved to the audio thread
+unsafe impl Send for LoadedPlugin {}
+
+impl LoadedPlugin {
+	pub fn load(slot: usize, config: &PluginConfig, sample_rate: u32, block_size: usize) -> Result<Self> {
+		let mut instance = match config.format {
+			PluginFormat::Vst2 => load_vst(Path::new(&config.path))?,
+		};
+		let info = instance.get_info();
+
+		instance.init();
+		instance.set_sample_rate(sample_rate as f32);
+		instance.set_block_size(block_size as i64);
+
+		let handle = PluginHandle {
+			slot,
+			parameter_count: info.parameters,
+			preset_chunks: info.preset_chunks,
+			parameters: instance.get_parameter_object(),
+		};
+		for (index, value) in config.parameters.iter().enumerate().take(info.parameters.max(0) as usize) {
+			handle.parameters.set_parameter(index as i32, *value);
+		}
+		if let (true, Some(chunk)) = (info.preset_chunks, &config.chunk) {
+			match base64::decode(chunk) {
+				Ok(data) => handle.parameters.load_preset_data(&data),
+				Err(err) => log::warn!("Invalid saved state for plugin {}: {}", config.path, err),
+			}
+		}
+
+		instance.resume();
+		log::info!("Loaded plugin {} ({})", info.name, config.path);
+
+		Ok(Self {
+			instance,
+			buffer: HostBuffer::new(info.inputs.max(0) as usize, info.outputs.max(0) as usize),
+			inputs: vec![vec![0.0; block_size]; info.inputs.max(0) as usize],
+			outputs: vec![vec![0.0; block_size]; info.outputs.max(0) as usize],
+			handle,
+		})
+	}
+
+	pub fn handle(&self) -> PluginHandle {
+		self.handle.clone()
+	}
+
+	// Processes a block of interleaved samples in place, plugin inputs and outputs are mapped onto the bus channels
+	pub fn process(&mut self, block: &mut [f32], channels: usize) {
+		let frames = block.len() / channels;
+		if self.outputs.is_empty() || frames > self.outputs[0].len() {
+			return;
+		}
+
+		for (idx, input) in self.inputs.iter_mut().enumerate() {
+			let channel = idx % channels;
+			for (frame, sample) in input.iter_mut().take(frames).enumerate() {
+				*sample = block[frame * channels + channel];
+			}
+		}
+
+		let mut audio_buffer = self.buffer.bind(&self.inputs, &mut self.outputs);
+		self.instance.process(&mut audio_buffer);
+
+		for channel in 0..channels {
+			let output = &self.outputs[channel % self.outputs.len()];
+			for (frame, sample) in output.iter().take(frames).enumerate() {
+				block[frame * channels + channel] = *sample;
+			}
+		}
+	}
+}
+
+impl Drop for LoadedPlugin {
+	fn drop(&mut self) {
+		self.instance.suspend();
+	}
+}
+
+pub type PluginChain = Vec<LoadedPlugin>;
+
+// Plugins that cannot be loaded are left out of the chain so that the device still plays
+pub fn load_chain(configs: &[PluginConfig], sample_rate: u32, block_size: usize) -> PluginChain {
+	configs
+		.iter()
+		.enumerate()
+		.filter(|(_, config)| !config.bypass)
+		.filter_map(|(slot, config)| match LoadedPlugin::load(slot, config, sample_rate, block_size) {
+			Ok(plugin) => Some(plugin),
+			Err(err) => {
+				log::error!("{}", err);
+				None
+			},
+		})
+		.collect()
+}
@@ -0,0 +1,134 @@
+use std::{
+	collections::{ HashMap, VecDeque },
+	time::{ Duration, Instant },
+};
+
+use serde::Serialize;
+
+use crate::config::RateLimitConfig;
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum BlockReason {
+	Cooldown, // The sound itself was played too recently
+	RateLimit, // Too many sounds were played recently
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Blocked {
+	pub reason: BlockReason,
+	pub remaining: Duration,
+}
+
+impl std::fmt::Display for Blocked {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		let what = match self.reason {
+			BlockReason::Cooldown => "The sound is on cooldown",
+			BlockReason::RateLimit => "Too many sounds were played",
+		};
+		write!(f, "{}, try again in {:.1} seconds", what, self.remaining.as_secs_f32())
+	}
+}
+
+impl std::error::Error for Blocked {}
+
+// Remembers recent plays to enforce per-sound cooldowns and the global rate limit
+#[derive(Default)]
+pub struct RateLimiter {
+	last_played: HashMap<String, Instant>,
+	recent: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+	// Records the play if it is allowed
+	pub fn try_play(&mut self, sound_id: &str, cooldown: Option<Duration>, config: &RateLimitConfig, now: Instant) -> Result<(), Blocked> {
+		if let (Some(cooldown), Some(last)) = (cooldown, self.last_played.get(sound_id)) {
+			let elapsed = now.saturating_duration_since(*last);
+			if elapsed < cooldown {
+				return Err(Blocked {
+					reason: BlockReason::Cooldown,
+					remaining: cooldown - elapsed,
+				});
+			}
+		}
+
+		let window = Duration::from_millis(config.window_ms);
+		while let Some(oldest) = self.recent.front() {
+			if now.saturating_duration_since(*oldest) < window {
+				break;
+			}
+			self.recent.pop_front();
+		}
+		if config.enabled && self.recent.len() >= config.max_plays as usize {
+			// A play is allowed again once the oldest play in the window expires, never if no play is allowed at all
+			let remaining = if config.max_plays == 0 {
+				window
+			} else {
+				let oldest = self.recent[self.recent.len() - config.max_plays as usize];
+				window - now.saturating_duration_since(oldest)
+			};
+			return Err(Blocked {
+				reason: BlockReason::RateLimit,
+				remaining,
+			});
+		}
+
+		self.last_played.insert(sound_id.to_owned(), now);
+		self.recent.push_back(now);
+		Ok(())
+	}
+}
+
+#[test]
+fn test_cooldown_and_rate_limit() {
+	let config = RateLimitConfig {
+		enabled: true,
+		max_plays: 2,
+		window_ms: 10_000,
+	};
+	let mut limiter = RateLimiter::default();
+	let start = Instant::now();
+	let at = |secs: u64| start + Duration::from_secs(secs);
+	let cooldown = Some(Duration::from_secs(5));
+
+	assert!(limiter.try_play("horn", cooldown, &config, at(0)).is_ok());
+	assert_eq!(limiter.try_play("horn", cooldown, &config, at(2)), Err(Blocked {
+		reason: BlockReason::Cooldown,
+		remaining: Duration::from_secs(3),
+	}));
+	assert!(limiter.try_play("clap", None, &config, at(3)).is_ok());
+	assert_eq!(limiter.try_play("drum", None, &config, at(4)), Err(Blocked {
+		reason: BlockReason::RateLimit,
+		remaining: Duration::from_secs(6),
+	}));
+	// Blocked attempts do not count against the limit
+	assert!(limiter.try_play("drum", None, &config, at(10)).is_ok());
+	assert!(limiter.try_play("horn", cooldown, &config, at(13)).is_ok());
+}
+
+#[test]
+fn test_rate_limit_without_plays_allowed() {
+	let config = RateLimitConfig {
+		enabled: true,
+		max_plays: 0,
+		window_ms: 10_000,
+	};
+	let mut limiter = RateLimiter::default();
+	assert_eq!(limiter.try_play("horn", None, &config, Instant::now()), Err(Blocked {
+		reason: BlockReason::RateLimit,
+		remaining: Duration::from_secs(10),
+	}));
+}
+
+#[test]
+fn test_rate_limit_disabled() {
+	let config = RateLimitConfig {
+		enabled: false,
+		..RateLimitConfig::default()
+	};
+	let mut limiter = RateLimiter::default();
+	let now = Instant::now();
+	for _ in 0..100 {
+		assert!(limiter.try_play("horn", None, &config, now).is_ok());
+	}
+}
@@ -0,0 +1,138 @@
+use std::{
+	fs::File,
+	io::BufWriter,
+	path::{ Path, PathBuf },
+	thread,
+	time::{ Duration, Instant },
+	sync::{
+		Arc, Mutex, mpsc,
+		atomic::{ AtomicBool, Ordering },
+	},
+};
+
+use anyhow::Result;
+use cpal::traits::{ DeviceTrait, HostTrait, StreamTrait };
+
+use crate::audio;
+use crate::config::DeviceId;
+
+type WavWriter = Arc<Mutex<Option<hound::WavWriter<BufWriter<File>>>>>;
+
+fn write_samples<T: cpal::Sample>(writer: &WavWriter, data: &[T]) {
+	if let Some(writer) = writer.lock().unwrap().as_mut() {
+		for sample in data {
+			if writer.write_sample(sample.to_i16()).is_err() {
+				break;
+			}
+		}
+	}
+}
+
+pub fn find_input_device(device_id: Option<&DeviceId>) -> Result<cpal::Device> {
+	match device_id {
+		Some(device_id) => audio::get_input_devices()?
+			.into_iter()
+			.find(|dev| device_id.matches(&dev.id))
+			.map(|dev| dev.device)
+			.ok_or_else(|| anyhow::Error::msg(format!("Input device {} not found", device_id))),
+		None => cpal::default_host()
+			.default_input_device()
+			.ok_or_else(|| anyhow::Error::msg("No default input device")),
+	}
+}
+
+// Records an input device to a WAV file until it is stopped or the maximum duration is reached
+pub struct Recording {
+	pub path: PathBuf,
+	stop: Arc<AtomicBool>,
+}
+
+impl Recording {
+	// `on_finished` is called from the recording thread once the file is complete
+	pub fn start<F>(device_id: Option<&DeviceId>, path: &Path, max_duration: Duration, on_finished: F) -> Result<Self>
+	where F: FnOnce(Result<PathBuf>) + Send + 'static {
+		let stop = Arc::new(AtomicBool::new(false));
+		let (tx, rx) = mpsc::channel();
+
+		// cpal streams cannot be moved across threads, so the stream lives on the recording thread
+		let thread_stop = stop.clone();
+		let thread_device_id = device_id.cloned();
+		let thread_path = path.to_owned();
+		thread::spawn(move || {
+			let writer: WavWriter = Arc::new(Mutex::new(None));
+			let open = || -> Result<cpal::Stream> {
+				let device = find_input_device(thread_device_id.as_ref())?;
+				let config = device.default_input_config()?;
+				let spec = hound::WavSpec {
+					channels: config.channels(),
+					sample_rate: config.sample_rate().0,
+					bits_per_sample: 16,
+					sample_format: hound::SampleFormat::Int,
+				};
+				*writer.lock().unwrap() = Some(hound::WavWriter::create(&thread_path, spec)?);
+
+				let err_fn = |err| log::error!("Recording stream error: {}", err);
+				let stream_writer = writer.clone();
+				let stream = match config.sample_format() {
+					cpal::SampleFormat::F32 => device.build_input_stream(
+						&config.into(),
+						move |data: &[f32], _: &_| write_samples(&stream_writer, data),
+						err_fn,
+					)?,
+					cpal::SampleFormat::I16 => device.build_input_stream(
+						&config.into(),
+						move |data: &[i16], _: &_| write_samples(&stream_writer, data),
+						err_fn,
+					)?,
+					cpal::SampleFormat::U16 => device.build_input_stream(
+						&config.into(),
+						move |data: &[u16], _: &_| write_samples(&stream_writer, data),
+						err_fn,
+					)?,
+				};
+				stream.play()?;
+				Ok(stream)
+			};
+
+			let stream = match open() {
+				Ok(stream) => stream,
+				Err(err) => {
+					if writer.lock().unwrap().take().is_some() {
+						let _ = std::fs::remove_file(&thread_path);
+					}
+					let _ = tx.send(Err(err));
+					return;
+				},
+			};
+			let _ = tx.send(Ok(()));
+
+			let started = Instant::now();
+			while !thread_stop.load(Ordering::SeqCst) {
+				if started.elapsed() >= max_duration {
+					log::warn!("Recording reached the maximum duration of {} seconds", max_duration.as_secs());
+					break;
+				}
+				thread::sleep(Duration::from_millis(50));
+			}
+			drop(stream);
+
+			let result = match writer.lock().unwrap().take() {
+				Some(writer) => writer.finalize().map(|_| thread_path).map_err(anyhow::Error::from),
+				None => Err(anyhow::Error::msg("Recording was not started")),
+			};
+			on_finished(result);
+		});
+
+		rx.recv()??;
+		log::info!("Recording to {}", path.display());
+
+		Ok(Self {
+			path: path.to_owned(),
+			stop,
+		})
+	}
+
+	pub fn stop(&self) {
+		self.stop.store(true, Ordering::SeqCst);
+	}
+}
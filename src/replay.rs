@@ -0,0 +1,236 @@
+use std::{
+	path::Path,
+	thread,
+	time::Duration,
+	sync::{
+		Arc, Mutex, mpsc,
+		atomic::{ AtomicBool, Ordering },
+	},
+};
+
+use anyhow::Result;
+use cpal::traits::{ DeviceTrait, StreamTrait };
+
+use crate::audio;
+use crate::recorder;
+use crate::config::ReplayConfig;
+
+// Longer replays would keep several hundred megabytes of samples in memory
+pub fn max_duration_secs() -> u32 {
+	600
+}
+
+// Keeps the most recent samples, older ones are overwritten
+pub struct RingBuffer {
+	samples: Vec<f32>,
+	write: usize,
+	filled: bool,
+}
+
+impl RingBuffer {
+	pub fn new(capacity: usize) -> Self {
+		Self {
+			samples: vec![0.0; capacity.max(1)],
+			write: 0,
+			filled: false,
+		}
+	}
+
+	pub fn push(&mut self, data: &[f32]) {
+		for sample in data {
+			self.samples[self.write] = *sample;
+			self.write += 1;
+			if self.write == self.samples.len() {
+				self.write = 0;
+				self.filled = true;
+			}
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		if self.filled { self.samples.len() } else { self.write }
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	// The last `count` samples, oldest first
+	pub fn last(&self, count: usize) -> Vec<f32> {
+		let count = count.min(self.len());
+		let start = (self.write + self.samples.len() - count) % self.samples.len();
+		if start + count <= self.samples.len() {
+			self.samples[start..start + count].to_vec()
+		} else {
+			let mut out = self.samples[start..].to_vec();
+			out.extend_from_slice(&self.samples[..count - out.len()]);
+			out
+		}
+	}
+}
+
+struct ReplayState {
+	buffer: RingBuffer,
+	channels: u16,
+	sample_rate: u32,
+}
+
+fn push_samples<T: cpal::Sample>(state: &Mutex<Option<ReplayState>>, data: &[T]) {
+	if let Some(state) = state.lock().unwrap().as_mut() {
+		let samples: Vec<f32> = data.iter().map(|sample| sample.to_f32()).collect();
+		state.buffer.push(&samples);
+	}
+}
+
+// Keeps the last seconds of an input device in memory so that they can be saved as a sound after the fact
+#[derive(Default)]
+pub struct InstantReplay {
+	stop: Arc<AtomicBool>,
+	state: Arc<Mutex<Option<ReplayState>>>,
+	thread_handle: Option<thread::JoinHandle<()>>,
+}
+
+impl InstantReplay {
+	pub fn start(&mut self, config: &ReplayConfig) -> Result<()> {
+		self.stop();
+		if !config.enabled {
+			return Ok(());
+		}
+
+		let stop = Arc::new(AtomicBool::new(false));
+		let (tx, rx) = mpsc::channel();
+		self.stop = stop.clone();
+
+		// cpal streams cannot be moved across threads, so the stream lives on its own thread
+		let state = self.state.clone();
+		let thread_config = config.clone();
+		self.thread_handle = Some(thread::spawn(move || {
+			let open = || -> Result<cpal::Stream> {
+				// Loopback captures what is played on an output device, where the host supports it
+				let (device, stream_config) = if thread_config.loopback {
+					let device_id = thread_config.device
+						.as_ref()
+						.ok_or_else(|| anyhow::Error::msg("Loopback capture needs an output device"))?;
+					let device = audio::get_output_devices()?
+						.into_iter()
+						.find(|dev| device_id.matches(&dev.id))
+						.ok_or_else(|| anyhow::Error::msg(format!("Output device {} not found", device_id)))?
+						.device;
+					let stream_config = device.default_output_config()?;
+					(device, stream_config)
+				} else {
+					let device = recorder::find_input_device(thread_config.device.as_ref())?;
+					let stream_config = device.default_input_config()?;
+					(device, stream_config)
+				};
+
+				let channels = stream_config.channels();
+				let sample_rate = stream_config.sample_rate().0;
+				let capacity = thread_config.duration_secs.clamp(1, max_duration_secs()) as usize * sample_rate as usize * channels as usize;
+				*state.lock().unwrap() = Some(ReplayState {
+					buffer: RingBuffer::new(capacity),
+					channels,
+					sample_rate,
+				});
+
+				let err_fn = |err| log::error!("Instant replay stream error: {}", err);
+				let stream_state = state.clone();
+				let stream = match stream_config.sample_format() {
+					cpal::SampleFormat::F32 => device.build_input_stream(
+						&stream_config.into(),
+						move |data: &[f32], _: &_| push_samples(&stream_state, data),
+						err_fn,
+					)?,
+					cpal::SampleFormat::I16 => device.build_input_stream(
+						&stream_config.into(),
+						move |data: &[i16], _: &_| push_samples(&stream_state, data),
+						err_fn,
+					)?,
+					cpal::SampleFormat::U16 => device.build_input_stream(
+						&stream_config.into(),
+						move |data: &[u16], _: &_| push_samples(&stream_state, data),
+						err_fn,
+					)?,
+				};
+				stream.play()?;
+				Ok(stream)
+			};
+
+			let _stream = match open() {
+				Ok(stream) => stream,
+				Err(err) => {
+					*state.lock().unwrap() = None;
+					let _ = tx.send(Err(err));
+					return;
+				},
+			};
+			let _ = tx.send(Ok(()));
+
+			while !stop.load(Ordering::SeqCst) {
+				thread::sleep(Duration::from_millis(100));
+			}
+		}));
+
+		rx.recv()??;
+		log::info!("Instant replay is keeping the last {} seconds", config.duration_secs);
+
+		Ok(())
+	}
+
+	pub fn stop(&mut self) {
+		self.stop.store(true, Ordering::SeqCst);
+		if let Some(handle) = self.thread_handle.take() {
+			let _ = handle.join();
+		}
+		*self.state.lock().unwrap() = None;
+	}
+
+	// Writes the last `seconds` (or the whole buffer) to a WAV file. The samples are copied out first so that
+	// capturing is not held up while the file is written
+	pub fn save(&self, path: &Path, seconds: Option<u32>) -> Result<()> {
+		let (samples, spec) = {
+			let state = self.state.lock().unwrap();
+			let state = state
+				.as_ref()
+				.ok_or_else(|| anyhow::Error::msg("Instant replay is not running"))?;
+			if state.buffer.is_empty() {
+				anyhow::bail!("Nothing has been captured yet");
+			}
+
+			let count = match seconds {
+				Some(seconds) => seconds as usize * state.sample_rate as usize * state.channels as usize,
+				None => state.buffer.len(),
+			};
+			let spec = hound::WavSpec {
+				channels: state.channels,
+				sample_rate: state.sample_rate,
+				bits_per_sample: 16,
+				sample_format: hound::SampleFormat::Int,
+			};
+			(state.buffer.last(count), spec)
+		};
+
+		let mut writer = hound::WavWriter::create(path, spec)?;
+		for sample in samples {
+			writer.write_sample(cpal::Sample::to_i16(&sample))?;
+		}
+		writer.finalize()?;
+		log::info!("Saved instant replay to {}", path.display());
+
+		Ok(())
+	}
+}
+
+#[test]
+fn test_ring_buffer_keeps_latest_samples() {
+	let mut buffer = RingBuffer::new(4);
+	assert!(buffer.is_empty());
+
+	buffer.push(&[1.0, 2.0, 3.0]);
+	assert_eq!(buffer.last(10), vec![1.0, 2.0, 3.0]);
+
+	buffer.push(&[4.0, 5.0, 6.0]);
+	assert_eq!(buffer.len(), 4);
+	assert_eq!(buffer.last(4), vec![3.0, 4.0, 5.0, 6.0]);
+	assert_eq!(buffer.last(2), vec![5.0, 6.0]);
+}
@@ -0,0 +1,304 @@
+use std::{
+	thread,
+	collections::HashMap,
+	time::Duration,
+	sync::{
+		Arc,
+		atomic::{ AtomicBool, Ordering },
+	},
+};
+
+use anyhow::Result;
+use chrono::{ Datelike, NaiveDate, NaiveDateTime, Timelike };
+
+use crate::config::{ Schedule, ScheduleRule };
+use crate::stats::PlaySource;
+
+// Abstracts the current time so that schedules can be tested without waiting
+pub trait Clock {
+	fn now(&self) -> NaiveDateTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+	fn now(&self) -> NaiveDateTime {
+		chrono::Local::now().naive_local()
+	}
+}
+
+// Parsed five field cron expression, each field is a bit set of the allowed values
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronExpression {
+	minutes: u64,
+	hours: u64,
+	days: u64,
+	months: u64,
+	weekdays: u64, // 0 is Sunday
+	any_day: bool,
+	any_weekday: bool,
+}
+
+fn has(bits: u64, value: u32) -> bool {
+	(bits & (1 << value)) != 0
+}
+
+// Supports `*`, single values, ranges (`1-5`), steps (`*/15`, `0-30/10`) and lists (`1,15`)
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+	let parse = |value: &str| -> Result<u32> {
+		match value.parse::<u32>() {
+			Ok(value) if value >= min && value <= max => Ok(value),
+			_ => Err(anyhow::Error::msg(format!("Invalid value \"{}\" in cron field \"{}\", expected {} to {}", value, field, min, max))),
+		}
+	};
+
+	let mut bits = 0;
+	for part in field.split(',') {
+		let mut split = part.splitn(2, '/');
+		let range = split.next().unwrap_or_default();
+		let step = match split.next() {
+			Some(step) => match step.parse::<usize>() {
+				Ok(step) if step > 0 => step,
+				_ => anyhow::bail!("Invalid step \"{}\" in cron field \"{}\"", step, field),
+			},
+			None => 1,
+		};
+
+		let (start, end) = if range == "*" {
+			(min, max)
+		} else if let Some(idx) = range.find('-') {
+			(parse(&range[..idx])?, parse(&range[idx + 1..])?)
+		} else {
+			let value = parse(range)?;
+			// `5/10` starts at 5 and goes on until the maximum
+			(value, if step > 1 { max } else { value })
+		};
+		if start > end {
+			anyhow::bail!("Invalid range \"{}\" in cron field \"{}\"", range, field);
+		}
+		for value in (start..=end).step_by(step) {
+			bits |= 1 << value;
+		}
+	}
+	Ok(bits)
+}
+
+impl CronExpression {
+	pub fn parse(expression: &str) -> Result<Self> {
+		let fields: Vec<&str> = expression.split_whitespace().collect();
+		if fields.len() != 5 {
+			anyhow::bail!("A cron expression has 5 fields: minute, hour, day of month, month and day of week");
+		}
+		// Sunday can be written as 0 or 7
+		let weekdays = parse_field(fields[4], 0, 7)?;
+		Ok(Self {
+			minutes: parse_field(fields[0], 0, 59)?,
+			hours: parse_field(fields[1], 0, 23)?,
+			days: parse_field(fields[2], 1, 31)?,
+			months: parse_field(fields[3], 1, 12)?,
+			weekdays: (weekdays | weekdays >> 7) & 0x7f,
+			any_day: fields[2].starts_with('*'),
+			any_weekday: fields[4].starts_with('*'),
+		})
+	}
+
+	// Like cron, a day matches either field when both the day of month and the day of week are restricted
+	fn matches_date(&self, date: NaiveDate) -> bool {
+		let day = has(self.days, date.day());
+		let weekday = has(self.weekdays, date.weekday().num_days_from_sunday());
+		let day_matches = if self.any_day || self.any_weekday { day && weekday } else { day || weekday };
+		day_matches && has(self.months, date.month())
+	}
+
+	// First matching minute strictly after `after`, None if there is none in the next few years (e.g. February 30th)
+	pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+		let mut time = after.date().and_hms(after.hour(), after.minute(), 0) + chrono::Duration::minutes(1);
+		let limit = time + chrono::Duration::days(4 * 366);
+		while time < limit {
+			if !self.matches_date(time.date()) {
+				time = (time.date() + chrono::Duration::days(1)).and_hms(0, 0, 0);
+			} else if !has(self.hours, time.hour()) {
+				time = time.date().and_hms(time.hour(), 0, 0) + chrono::Duration::hours(1);
+			} else if !has(self.minutes, time.minute()) {
+				time += chrono::Duration::minutes(1);
+			} else {
+				return Some(time);
+			}
+		}
+		None
+	}
+}
+
+// Next time the rule fires strictly after `after`
+pub fn next_run(rule: &ScheduleRule, after: NaiveDateTime) -> Result<Option<NaiveDateTime>> {
+	match rule {
+		ScheduleRule::Interval { every_secs: 0 } => anyhow::bail!("The interval must be at least one second"),
+		ScheduleRule::Interval { every_secs } => Ok(Some(after + chrono::Duration::seconds(*every_secs as i64))),
+		ScheduleRule::Cron { expression } => Ok(CronExpression::parse(expression)?.next_after(after)),
+	}
+}
+
+// Remembers when each schedule runs next
+#[derive(Default)]
+pub struct ScheduleTracker {
+	next_runs: HashMap<String, (ScheduleRule, Option<NaiveDateTime>)>,
+}
+
+impl ScheduleTracker {
+	// New and modified schedules start counting from now. A run that was missed, e.g. while the computer was asleep,
+	// happens once as soon as possible instead of being repeated for every missed occurrence
+	pub fn due<'a>(&mut self, schedules: &'a [Schedule], clock: &dyn Clock) -> Vec<&'a Schedule> {
+		let now = clock.now();
+		self.next_runs.retain(|id, _| schedules.iter().any(|schedule| schedule.enabled && &schedule.id == id));
+
+		let mut due = Vec::new();
+		for schedule in schedules.iter().filter(|schedule| schedule.enabled) {
+			match self.next_runs.get_mut(&schedule.id) {
+				Some((rule, next)) if *rule == schedule.rule => {
+					if let Some(time) = next {
+						if *time <= now {
+							due.push(schedule);
+							*next = next_run(rule, now).ok().flatten();
+						}
+					}
+				},
+				_ => {
+					let next = match next_run(&schedule.rule, now) {
+						Ok(next) => next,
+						Err(err) => {
+							log::warn!("Invalid rule for schedule {}: {}", schedule.name, err);
+							None
+						},
+					};
+					self.next_runs.insert(schedule.id.clone(), (schedule.rule.clone(), next));
+				},
+			}
+		}
+		due
+	}
+}
+
+#[derive(Default)]
+pub struct Scheduler {
+	stop: Arc<AtomicBool>,
+	thread_handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Scheduler {
+	fn poll_interval() -> Duration {
+		Duration::from_secs(1)
+	}
+
+	pub fn start(&mut self, app: Arc<crate::App>) {
+		let stop = self.stop.clone();
+
+		self.thread_handle = Some(thread::spawn(move || {
+			let clock = SystemClock;
+			let mut tracker = ScheduleTracker::default();
+
+			while !stop.load(Ordering::SeqCst) {
+				let schedules = app.config.lock().unwrap().schedules.clone();
+				for schedule in tracker.due(&schedules, &clock) {
+					let played = app.websocket_server
+						.lock().unwrap()
+						.play_sound_by_id(&schedule.sound, PlaySource::Schedule);
+					match played {
+						Ok(true) => log::info!("Played scheduled sound {}", schedule.name),
+						Ok(false) => log::warn!("The sound of schedule {} does not exist", schedule.name),
+						Err(blocked) => log::info!("Scheduled sound {} was not played: {}", schedule.name, blocked),
+					}
+				}
+				thread::sleep(Self::poll_interval());
+			}
+		}));
+	}
+
+	pub fn stop(&mut self) {
+		log::info!("Stopping scheduler...");
+
+		self.stop.store(true, Ordering::SeqCst);
+		if let Some(handle) = self.thread_handle.take() {
+			let _ = handle.join();
+		}
+	}
+}
+
+#[cfg(test)]
+struct ManualClock(std::cell::Cell<NaiveDateTime>);
+
+#[cfg(test)]
+impl Clock for ManualClock {
+	fn now(&self) -> NaiveDateTime {
+		self.0.get()
+	}
+}
+
+#[cfg(test)]
+fn time(date: &str) -> NaiveDateTime {
+	NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").unwrap()
+}
+
+#[test]
+fn test_cron_next_after() {
+	let next = |expression: &str, after: &str| CronExpression::parse(expression).unwrap().next_after(time(after));
+
+	assert_eq!(next("*/30 * * * *", "2021-03-01 10:29:59"), Some(time("2021-03-01 10:30:00")));
+	assert_eq!(next("*/30 * * * *", "2021-03-01 10:30:00"), Some(time("2021-03-01 11:00:00")));
+	assert_eq!(next("0 20 * * *", "2021-03-01 20:00:00"), Some(time("2021-03-02 20:00:00")));
+	// 2021-03-06 is a Saturday
+	assert_eq!(next("15 9 * * 1-5", "2021-03-06 08:00:00"), Some(time("2021-03-08 09:15:00")));
+	assert_eq!(next("0 0 * * 7", "2021-03-01 00:00:00"), Some(time("2021-03-07 00:00:00")));
+	// Either the 1st of the month or a Friday
+	assert_eq!(next("0 12 1 * 5", "2021-03-01 13:00:00"), Some(time("2021-03-05 12:00:00")));
+	assert_eq!(next("0 0 29 2 *", "2021-03-01 00:00:00"), Some(time("2024-02-29 00:00:00")));
+	assert_eq!(next("0 0 30 2 *", "2021-03-01 00:00:00"), None);
+
+	assert!(CronExpression::parse("* * * *").is_err());
+	assert!(CronExpression::parse("60 * * * *").is_err());
+	assert!(CronExpression::parse("*/0 * * * *").is_err());
+	assert!(CronExpression::parse("5-1 * * * *").is_err());
+}
+
+#[test]
+fn test_schedule_tracker() {
+	let clock = ManualClock(std::cell::Cell::new(time("2021-03-01 10:00:00")));
+	let advance = |secs: i64| clock.0.set(clock.0.get() + chrono::Duration::seconds(secs));
+	let mut schedules = vec![
+		Schedule {
+			id: "hydrate".to_owned(),
+			rule: ScheduleRule::Interval { every_secs: 1800 },
+			..Schedule::default()
+		},
+		Schedule {
+			id: "sting".to_owned(),
+			rule: ScheduleRule::Cron { expression: "0 11 * * *".to_owned() },
+			..Schedule::default()
+		},
+	];
+	let mut tracker = ScheduleTracker::default();
+	let due = |tracker: &mut ScheduleTracker, schedules: &[Schedule]| -> Vec<String> {
+		tracker.due(schedules, &clock).iter().map(|schedule| schedule.id.clone()).collect()
+	};
+
+	assert!(due(&mut tracker, &schedules).is_empty());
+	advance(1799);
+	assert!(due(&mut tracker, &schedules).is_empty());
+	advance(1);
+	assert_eq!(due(&mut tracker, &schedules), vec!["hydrate"]);
+	advance(1800);
+	assert_eq!(due(&mut tracker, &schedules), vec!["hydrate", "sting"]);
+
+	// Missed runs only happen once
+	advance(5 * 3600);
+	assert_eq!(due(&mut tracker, &schedules), vec!["hydrate"]);
+	assert!(due(&mut tracker, &schedules).is_empty());
+
+	// Changing the rule restarts the schedule
+	schedules[0].rule = ScheduleRule::Interval { every_secs: 60 };
+	assert!(due(&mut tracker, &schedules).is_empty());
+	advance(60);
+	assert_eq!(due(&mut tracker, &schedules), vec!["hydrate"]);
+	schedules[0].enabled = false;
+	advance(60);
+	assert!(due(&mut tracker, &schedules).is_empty());
+}
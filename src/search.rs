@@ -0,0 +1,200 @@
+use std::path::Path;
+
+use serde::{ Serialize, Deserialize };
+
+use crate::config::Sound;
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum SortOrder {
+	Relevance, // Best matches first, library order without a query
+	Name,
+	RecentlyAdded,
+	MostPlayed,
+}
+
+// Not derived, `#[default]` on enum variants needs Rust 1.62
+#[allow(clippy::derivable_impls)]
+impl Default for SortOrder {
+	fn default() -> Self {
+		Self::Relevance
+	}
+}
+
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Deserialize)]
+pub struct SearchQuery {
+	pub query: String,
+	pub sort: SortOrder,
+	pub favourites_only: bool,
+	pub page: usize, // Starts at 0
+	pub page_size: usize,
+}
+
+impl Default for SearchQuery {
+	fn default() -> Self {
+		Self {
+			query: String::new(),
+			sort: SortOrder::default(),
+			favourites_only: false,
+			page: 0,
+			page_size: 50,
+		}
+	}
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Serialize)]
+pub struct SearchResults<'a> {
+	pub sounds: Vec<&'a Sound>,
+	pub total: usize,
+	pub page: usize,
+	pub page_count: usize,
+}
+
+// Scores how well `pattern` matches `text` when its characters appear in order, None if they do not.
+// Consecutive characters and characters at the start of words score higher
+fn fuzzy_score(pattern: &str, text: &str) -> Option<u32> {
+	let text: Vec<char> = text.to_lowercase().chars().collect();
+	let mut score = 0;
+	let mut pos = 0;
+	let mut prev_match: Option<usize> = None;
+
+	for c in pattern.to_lowercase().chars() {
+		let idx = pos + text[pos..].iter().position(|t| *t == c)?;
+		score += 1;
+		if prev_match.is_some() && prev_match == idx.checked_sub(1) {
+			score += 5;
+		}
+		if idx == 0 || !text[idx - 1].is_alphanumeric() {
+			score += 3;
+		}
+		prev_match = Some(idx);
+		pos = idx + 1;
+	}
+
+	// Exact substrings beat scattered matches of the same length
+	if text.iter().collect::<String>().contains(&pattern.to_lowercase()) {
+		score += 10;
+	}
+	Some(score)
+}
+
+// Every word of the query must match the name, a tag or the file name
+fn match_score(words: &[&str], sound: &Sound) -> Option<u32> {
+	let file_name = Path::new(&sound.path)
+		.file_name()
+		.map(|name| name.to_string_lossy().into_owned())
+		.unwrap_or_default();
+
+	let mut total = 0;
+	for word in words {
+		let name = fuzzy_score(word, &sound.name).map(|score| score * 2);
+		let tags = sound.tags.iter().filter_map(|tag| fuzzy_score(word, tag)).max();
+		let file = fuzzy_score(word, &file_name);
+		total += name.max(tags).max(file)?;
+	}
+	Some(total)
+}
+
+// Pages are capped so that a client cannot ask for the whole library at once
+fn max_page_size() -> usize {
+	500
+}
+
+// Sounds are appended to the library when they are added, so their position tells which ones are the most recent
+pub fn search<'a>(sounds: &'a [Sound], query: &SearchQuery, play_count: impl Fn(&Sound) -> u64) -> SearchResults<'a> {
+	let words: Vec<&str> = query.query.split_whitespace().collect();
+	let mut matches: Vec<(usize, u32, &Sound)> = sounds
+		.iter()
+		.enumerate()
+		.filter(|(_, sound)| !query.favourites_only || sound.favourite)
+		.filter_map(|(idx, sound)| match_score(&words, sound).map(|score| (idx, score, sound)))
+		.collect();
+
+	match query.sort {
+		SortOrder::Relevance => matches.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0))),
+		SortOrder::Name => matches.sort_by_cached_key(|(_, _, sound)| sound.name.to_lowercase()),
+		SortOrder::RecentlyAdded => matches.sort_by_key(|(idx, _, _)| std::cmp::Reverse(*idx)),
+		SortOrder::MostPlayed => matches.sort_by_cached_key(|(idx, _, sound)| (std::cmp::Reverse(play_count(sound)), *idx)),
+	}
+
+	let page_size = query.page_size.clamp(1, max_page_size());
+	let total = matches.len();
+	SearchResults {
+		sounds: matches
+			.into_iter()
+			.skip(query.page.saturating_mul(page_size))
+			.take(page_size)
+			.map(|(_, _, sound)| sound)
+			.collect(),
+		total,
+		page: query.page,
+		page_count: if total == 0 { 0 } else { (total - 1) / page_size + 1 },
+	}
+}
+
+#[cfg(test)]
+fn names(results: &SearchResults) -> Vec<String> {
+	results.sounds.iter().map(|sound| sound.name.clone()).collect()
+}
+
+#[test]
+fn test_fuzzy_search_matches_names_tags_and_files() {
+	let sounds = vec![
+		Sound::new("Air horn".to_owned(), "sounds/horn.mp3".to_owned()),
+		Sound {
+			tags: vec!["meme".to_owned()],
+			..Sound::new("Sad trombone".to_owned(), "sounds/wah.mp3".to_owned())
+		},
+		Sound::new("Applause".to_owned(), "sounds/clap-track.wav".to_owned()),
+	];
+	let query = |query: &str| SearchQuery {
+		query: query.to_owned(),
+		..SearchQuery::default()
+	};
+
+	assert_eq!(names(&search(&sounds, &query("ahrn"), |_| 0)), vec!["Air horn"]);
+	assert_eq!(names(&search(&sounds, &query("meme"), |_| 0)), vec!["Sad trombone"]);
+	assert_eq!(names(&search(&sounds, &query("clap"), |_| 0)), vec!["Applause"]);
+	assert_eq!(names(&search(&sounds, &query("horn sad"), |_| 0)), Vec::<String>::new());
+	// An exact match ranks above a scattered one
+	assert_eq!(names(&search(&sounds, &query("a"), |_| 0))[0], "Air horn");
+	assert_eq!(search(&sounds, &query(""), |_| 0).total, 3);
+}
+
+#[test]
+fn test_search_sorts_filters_and_pages() {
+	let mut sounds: Vec<Sound> = ["b", "c", "a", "d", "e"]
+		.iter()
+		.map(|name| Sound::new(name.to_string(), format!("{}.mp3", name)))
+		.collect();
+	sounds[3].favourite = true;
+	sounds[4].favourite = true;
+	let plays = |sound: &Sound| if sound.name == "c" { 10 } else { 0 };
+
+	let mut query = SearchQuery {
+		sort: SortOrder::Name,
+		page_size: 2,
+		..SearchQuery::default()
+	};
+	let results = search(&sounds, &query, plays);
+	assert_eq!((names(&results), results.total, results.page_count), (vec!["a".to_owned(), "b".to_owned()], 5, 3));
+	query.page = 2;
+	assert_eq!(names(&search(&sounds, &query, plays)), vec!["e"]);
+
+	query.page = 0;
+	query.sort = SortOrder::RecentlyAdded;
+	assert_eq!(names(&search(&sounds, &query, plays)), vec!["e", "d"]);
+	query.sort = SortOrder::MostPlayed;
+	assert_eq!(names(&search(&sounds, &query, plays)), vec!["c", "b"]);
+	query.favourites_only = true;
+	assert_eq!(names(&search(&sounds, &query, plays)), vec!["d", "e"]);
+
+	// Out of range pages are empty instead of overflowing
+	query.page = usize::MAX;
+	query.page_size = usize::MAX;
+	let results = search(&sounds, &query, plays);
+	assert_eq!((results.sounds.len(), results.page_count), (0, 1));
+}
@@ -0,0 +1,47 @@
+use std::{ ptr, thread, sync::mpsc };
+
+use anyhow::Result;
+use winapi::um::{ errhandlingapi, processthreadsapi, winuser };
+
+// A system-wide keyboard shortcut that is released when dropped, which the hotkey crate cannot do
+pub struct GlobalShortcut {
+	thread_id: u32,
+}
+
+impl GlobalShortcut {
+	// `modifiers` are the winuser MOD_* flags and `key` a virtual-key code
+	pub fn register(modifiers: u32, key: u32, handler: impl Fn() + Send + 'static) -> Result<Self> {
+		let (tx, rx) = mpsc::channel();
+
+		// Hotkeys registered without a window belong to the thread that registered them
+		thread::spawn(move || unsafe {
+			if winuser::RegisterHotKey(ptr::null_mut(), 1, modifiers | winuser::MOD_NOREPEAT as u32, key) == 0 {
+				let _ = tx.send(Err(anyhow::Error::msg(format!("Could not RegisterHotKey: {}", errhandlingapi::GetLastError()))));
+				return;
+			}
+			let _ = tx.send(Ok(processthreadsapi::GetCurrentThreadId()));
+
+			// GetMessageW returns 0 once WM_QUIT is posted by drop
+			let mut msg: winuser::MSG = std::mem::zeroed();
+			while winuser::GetMessageW(&mut msg, ptr::null_mut(), 0, 0) > 0 {
+				if msg.message == winuser::WM_HOTKEY {
+					handler();
+				}
+			}
+			winuser::UnregisterHotKey(ptr::null_mut(), 1);
+		});
+
+		Ok(Self {
+			thread_id: rx.recv()??,
+		})
+	}
+}
+
+// The thread is not joined, it may be waiting on a lock held by whoever drops the shortcut
+impl Drop for GlobalShortcut {
+	fn drop(&mut self) {
+		unsafe {
+			winuser::PostThreadMessageW(self.thread_id, winuser::WM_QUIT, 0, 0);
+		}
+	}
+}
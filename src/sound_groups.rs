@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use crate::config::SoundGroup;
+
+// Small xorshift64* generator, good enough to pick sounds and seedable for tests
+struct Rng {
+	state: u64,
+}
+
+impl Rng {
+	fn new(seed: u64) -> Self {
+		Self {
+			// The state must never be zero
+			state: if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed },
+		}
+	}
+
+	fn next_u64(&mut self) -> u64 {
+		self.state ^= self.state >> 12;
+		self.state ^= self.state << 25;
+		self.state ^= self.state >> 27;
+		self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+	}
+
+	fn below(&mut self, n: usize) -> usize {
+		(self.next_u64() % n as u64) as usize
+	}
+
+	fn shuffle<T>(&mut self, items: &mut [T]) {
+		for i in (1..items.len()).rev() {
+			items.swap(i, self.below(i + 1));
+		}
+	}
+}
+
+pub struct GroupPicker {
+	rng: Rng,
+	bags: HashMap<String, Vec<String>>,
+	last_picked: HashMap<String, String>,
+}
+
+impl Default for GroupPicker {
+	fn default() -> Self {
+		let seed = std::time::SystemTime::now()
+			.duration_since(std::time::UNIX_EPOCH)
+			.map(|d| d.as_nanos() as u64)
+			.unwrap_or_default();
+		Self::with_seed(seed)
+	}
+}
+
+impl GroupPicker {
+	pub fn with_seed(seed: u64) -> Self {
+		Self {
+			rng: Rng::new(seed),
+			bags: HashMap::new(),
+			last_picked: HashMap::new(),
+		}
+	}
+
+	pub fn pick(&mut self, group: &SoundGroup) -> Option<String> {
+		if group.sounds.is_empty() {
+			return None;
+		}
+
+		let picked = if group.avoid_repeats {
+			self.pick_from_bag(group)
+		} else {
+			group.sounds[self.rng.below(group.sounds.len())].clone()
+		};
+		self.last_picked.insert(group.name.clone(), picked.clone());
+
+		Some(picked)
+	}
+
+	fn pick_from_bag(&mut self, group: &SoundGroup) -> String {
+		let bag = self.bags.entry(group.name.clone()).or_default();
+		// Drop sounds that were removed from the group since the bag was filled
+		bag.retain(|sound| group.sounds.contains(sound));
+
+		if bag.is_empty() {
+			bag.extend(group.sounds.iter().cloned());
+			self.rng.shuffle(bag);
+
+			// Don't play the same sound twice in a row when starting a new round
+			if let Some(last) = self.last_picked.get(&group.name) {
+				let last_idx = bag.len() - 1;
+				if last_idx > 0 && bag[last_idx] == *last {
+					bag.swap(0, last_idx);
+				}
+			}
+		}
+
+		bag.pop().unwrap()
+	}
+}
+
+// Groups reference the ids of the sounds
+#[cfg(test)]
+fn sound_ids(count: usize) -> Vec<String> {
+	(0..count)
+		.map(|i| crate::config::Sound::new(i.to_string(), format!("sounds/{}.mp3", i)).id)
+		.collect()
+}
+
+#[test]
+fn test_group_picker_is_deterministic() {
+	let group = SoundGroup {
+		name: "reactions".into(),
+		sounds: sound_ids(10),
+		avoid_repeats: false,
+	};
+
+	let mut a = GroupPicker::with_seed(42);
+	let mut b = GroupPicker::with_seed(42);
+	for _ in 0..50 {
+		assert_eq!(a.pick(&group), b.pick(&group));
+	}
+
+	let empty = SoundGroup { name: "empty".into(), ..SoundGroup::default() };
+	assert_eq!(a.pick(&empty), None);
+}
+
+#[test]
+fn test_group_picker_shuffle_bag() {
+	let mut group = SoundGroup {
+		name: "reactions".into(),
+		sounds: sound_ids(5),
+		avoid_repeats: true,
+	};
+	let mut picker = GroupPicker::with_seed(7);
+	let mut sorted = group.sounds.clone();
+	sorted.sort();
+
+	let mut previous = None;
+	for _ in 0..20 {
+		let mut round: Vec<String> = (0..5).map(|_| picker.pick(&group).unwrap()).collect();
+		assert_ne!(previous.as_ref(), round.first());
+		previous = round.last().cloned();
+		round.sort();
+		assert_eq!(round, sorted);
+	}
+
+	// Sounds removed from the group are never picked
+	group.sounds.truncate(2);
+	for _ in 0..10 {
+		assert!(group.sounds.contains(&picker.pick(&group).unwrap()));
+	}
+}
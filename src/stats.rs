@@ -0,0 +1,264 @@
+use std::{
+	fs::{ File, OpenOptions },
+	io::{ BufRead, BufReader, Write },
+	collections::{ HashMap, VecDeque },
+	path::PathBuf,
+};
+
+use serde::{ Serialize, Deserialize };
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum PlaySource {
+	Click,
+	Hotkey,
+	Group,
+	Api,
+	Schedule,
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PlayEvent {
+	pub time: i64, // Unix timestamp in seconds
+	pub sound: String, // Id of the sound
+	pub source: PlaySource,
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SoundStats {
+	pub count: u64,
+	pub last_played: Option<i64>,
+}
+
+// Plays that were folded into a single line when the history was compacted
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+struct SoundTotal {
+	sound: String,
+	#[serde(flatten)]
+	stats: SoundStats,
+}
+
+#[serde(untagged)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+enum HistoryLine {
+	Event(PlayEvent),
+	Total(SoundTotal),
+}
+
+// Play history, appended to its own file so that config.json is not rewritten on every play
+#[derive(Default)]
+pub struct PlayStats {
+	file_path: Option<PathBuf>,
+	lines: usize, // Lines in the file, to know when to compact it
+	sounds: HashMap<String, SoundStats>,
+	recent: VecDeque<PlayEvent>,
+}
+
+impl PlayStats {
+	fn recent_capacity() -> usize {
+		100
+	}
+
+	fn history_file_path() -> std::io::Result<PathBuf> {
+		let mut path = crate::App::get_working_directory()?;
+		path.push("play_history.jsonl");
+		Ok(path)
+	}
+
+	pub fn read() -> Self {
+		match Self::history_file_path() {
+			Ok(path) => Self::read_from(path),
+			Err(err) => {
+				log::warn!("Could not find the play history: {}", err);
+				Self::default()
+			},
+		}
+	}
+
+	// Lines that cannot be parsed are skipped, e.g. one that was cut short by a crash
+	pub fn read_from(path: PathBuf) -> Self {
+		let mut stats = Self::default();
+		if let Ok(file) = File::open(&path) {
+			for line in BufReader::new(file).lines() {
+				let line = match line {
+					Ok(line) => line,
+					Err(_) => break,
+				};
+				stats.lines += 1;
+				match serde_json::from_str(&line) {
+					Ok(HistoryLine::Event(event)) => stats.add(event),
+					Ok(HistoryLine::Total(total)) => stats.add_total(total),
+					Err(_) => { },
+				}
+			}
+		}
+		stats.file_path = Some(path);
+		if stats.lines > stats.max_lines() {
+			stats.compact();
+		}
+		stats
+	}
+
+	fn add(&mut self, event: PlayEvent) {
+		let sound = self.sounds.entry(event.sound.clone()).or_default();
+		sound.count += 1;
+		sound.last_played = sound.last_played.max(Some(event.time));
+
+		if self.recent.len() == Self::recent_capacity() {
+			self.recent.pop_front();
+		}
+		self.recent.push_back(event);
+	}
+
+	fn add_total(&mut self, total: SoundTotal) {
+		let sound = self.sounds.entry(total.sound).or_default();
+		sound.count += total.stats.count;
+		sound.last_played = sound.last_played.max(total.stats.last_played);
+	}
+
+	// The file is compacted once it holds twice as many lines as a compacted file would
+	fn max_lines(&self) -> usize {
+		2 * (self.sounds.len() + Self::recent_capacity())
+	}
+
+	// Rewrites the file as one total per sound followed by the recent plays, which are not counted in the totals
+	fn compact(&mut self) {
+		let path = match &self.file_path {
+			Some(path) => path.clone(),
+			None => return,
+		};
+
+		let mut totals = self.sounds.clone();
+		for event in self.recent.iter() {
+			if let Some(total) = totals.get_mut(&event.sound) {
+				total.count -= 1;
+			}
+		}
+		let mut lines: Vec<HistoryLine> = totals
+			.into_iter()
+			.filter(|(_, stats)| stats.count > 0)
+			.map(|(sound, stats)| HistoryLine::Total(SoundTotal { sound, stats }))
+			.collect();
+		lines.extend(self.recent.iter().cloned().map(HistoryLine::Event));
+
+		// Written next to the history first so that a crash cannot lose it
+		let tmp_path = path.with_extension("jsonl.tmp");
+		let res = File::create(&tmp_path)
+			.and_then(|mut file| {
+				for line in lines.iter() {
+					writeln!(file, "{}", serde_json::to_string(line)?)?;
+				}
+				file.sync_all()
+			})
+			.and_then(|_| std::fs::rename(&tmp_path, &path));
+		match res {
+			Ok(()) => self.lines = lines.len(),
+			Err(err) => log::error!("Could not compact the play history: {}", err),
+		}
+	}
+
+	pub fn record(&mut self, sound_id: &str, source: PlaySource) {
+		let event = PlayEvent {
+			time: chrono::Utc::now().timestamp(),
+			sound: sound_id.to_owned(),
+			source,
+		};
+
+		if let Some(path) = &self.file_path {
+			let res = OpenOptions::new()
+				.create(true)
+				.append(true)
+				.open(path)
+				.and_then(|mut file| writeln!(file, "{}", serde_json::to_string(&event)?));
+			if let Err(err) = res {
+				log::error!("Could not save the play history: {}", err);
+			}
+			self.lines += 1;
+		}
+		self.add(event);
+		if self.lines > self.max_lines() {
+			self.compact();
+		}
+	}
+
+	// Forgets the plays of sounds that no longer exist
+	pub fn retain_sounds(&mut self, mut keep: impl FnMut(&str) -> bool) {
+		let before = self.sounds.len();
+		self.sounds.retain(|id, _| keep(id));
+		if self.sounds.len() != before {
+			let sounds = &self.sounds;
+			self.recent.retain(|event| sounds.contains_key(&event.sound));
+			self.compact();
+		}
+	}
+
+	pub fn play_count(&self, sound_id: &str) -> u64 {
+		self.sounds.get(sound_id).map(|stats| stats.count).unwrap_or(0)
+	}
+
+	pub fn sounds(&self) -> &HashMap<String, SoundStats> {
+		&self.sounds
+	}
+
+	// Ids of the last sounds played, most recent first and without repeats
+	pub fn recently_played(&self, limit: usize) -> Vec<&str> {
+		let mut ids: Vec<&str> = Vec::new();
+		for event in self.recent.iter().rev() {
+			if ids.len() == limit {
+				break;
+			}
+			if !ids.contains(&event.sound.as_str()) {
+				ids.push(&event.sound);
+			}
+		}
+		ids
+	}
+}
+
+#[test]
+fn test_play_stats_are_persisted() {
+	let path = std::env::temp_dir().join(format!("soundboard-stats-test-{}.jsonl", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+
+	let mut stats = PlayStats::read_from(path.clone());
+	stats.record("a", PlaySource::Click);
+	stats.record("b", PlaySource::Api);
+	stats.record("a", PlaySource::Hotkey);
+	assert_eq!(stats.recently_played(10), vec!["a", "b"]);
+	assert_eq!(stats.recently_played(1), vec!["a"]);
+
+	let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+	writeln!(file, "{{\"time\": 1, \"sou").unwrap();
+	let stats = PlayStats::read_from(path.clone());
+	assert_eq!((stats.play_count("a"), stats.play_count("b"), stats.play_count("c")), (2, 1, 0));
+	assert!(stats.sounds()["a"].last_played.is_some());
+
+	let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_play_history_is_compacted() {
+	let path = std::env::temp_dir().join(format!("soundboard-stats-compact-test-{}.jsonl", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+
+	let mut stats = PlayStats::read_from(path.clone());
+	for i in 0..1_000 {
+		stats.record(if i % 4 == 0 { "a" } else { "b" }, PlaySource::Click);
+	}
+	let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+	assert!(lines <= 2 * (2 + PlayStats::recent_capacity()), "{} lines", lines);
+
+	let mut stats = PlayStats::read_from(path.clone());
+	assert_eq!((stats.play_count("a"), stats.play_count("b")), (250, 750));
+	assert_eq!(stats.recently_played(10), vec!["b", "a"]);
+
+	stats.retain_sounds(|id| id != "a");
+	let stats = PlayStats::read_from(path.clone());
+	assert_eq!((stats.play_count("a"), stats.play_count("b")), (0, 750));
+	assert_eq!(stats.recently_played(10), vec!["b"]);
+
+	let _ = std::fs::remove_file(&path);
+}
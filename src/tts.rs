@@ -0,0 +1,157 @@
+use std::{
+	io::Write,
+	path::{ Path, PathBuf },
+	process::{ Command, Stdio },
+};
+
+use anyhow::Result;
+
+use crate::config::{ Sound, Speech, TtsConfig };
+
+pub trait TtsBackend {
+	// Identifies the backend and its settings in the cache, so that changing them renders the speech again
+	fn id(&self) -> String;
+	fn render(&self, speech: &Speech, output: &Path) -> Result<()>;
+}
+
+// Runs an espeak-ng compatible command line program
+pub struct CommandBackend {
+	program: String,
+}
+
+impl CommandBackend {
+	pub fn new(program: &str) -> Self {
+		Self {
+			program: program.to_owned(),
+		}
+	}
+}
+
+impl TtsBackend for CommandBackend {
+	fn id(&self) -> String {
+		self.program.clone()
+	}
+
+	fn render(&self, speech: &Speech, output: &Path) -> Result<()> {
+		let mut child = Command::new(&self.program)
+			.arg("-v").arg(&speech.voice)
+			.arg("-s").arg(speech.rate.to_string())
+			.arg("-p").arg(speech.pitch.to_string())
+			.arg("-w").arg(output)
+			.arg("--stdin") // The text could start with a dash and be read as an option
+			.stdin(Stdio::piped())
+			.stdout(Stdio::null())
+			.stderr(Stdio::piped())
+			.spawn()
+			.map_err(|err| anyhow::Error::msg(format!("Could not run {}: {}", self.program, err)))?;
+
+		if let Some(mut stdin) = child.stdin.take() {
+			stdin.write_all(speech.text.as_bytes())?;
+		}
+		let result = child.wait_with_output()?;
+		if !result.status.success() {
+			anyhow::bail!("{} failed: {}", self.program, String::from_utf8_lossy(&result.stderr).trim());
+		}
+
+		Ok(())
+	}
+}
+
+pub fn backend(config: &TtsConfig) -> Box<dyn TtsBackend> {
+	Box::new(CommandBackend::new(&config.command))
+}
+
+// FNV-1a, the file names must stay the same across builds for the cache to be reused
+fn hash(data: &str) -> u64 {
+	data.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+pub fn cache_file_name(backend: &dyn TtsBackend, speech: &Speech) -> String {
+	let key = format!("{}\n{}\n{}\n{}\n{}", backend.id(), speech.voice, speech.rate, speech.pitch, speech.text);
+	format!("{:016x}.wav", hash(&key))
+}
+
+pub fn cache_directory() -> Result<PathBuf> {
+	let mut path = crate::App::get_working_directory()?;
+	path.push("tts");
+	Ok(path)
+}
+
+// Returns the path of the rendered speech, only running the backend if it is not cached yet
+pub fn render_cached(backend: &dyn TtsBackend, speech: &Speech, cache_dir: &Path) -> Result<PathBuf> {
+	if speech.text.trim().is_empty() {
+		anyhow::bail!("Cannot render empty text to speech");
+	}
+
+	let path = cache_dir.join(cache_file_name(backend, speech));
+	if !path.exists() {
+		std::fs::create_dir_all(cache_dir)?;
+		// Render to a temporary file so that a failed render is not mistaken for a cached one
+		let tmp_path = path.with_extension("tmp.wav");
+		if let Err(err) = backend.render(speech, &tmp_path) {
+			let _ = std::fs::remove_file(&tmp_path);
+			return Err(err);
+		}
+		std::fs::rename(&tmp_path, &path)?;
+		log::info!("Rendered speech to {}", path.display());
+	}
+
+	Ok(path)
+}
+
+// Renders the speech of a sound again if its file was removed from the cache
+pub fn ensure_rendered(config: &TtsConfig, sound: &Sound) -> Result<()> {
+	let speech = match &sound.tts {
+		Some(speech) => speech,
+		None => return Ok(()),
+	};
+	let path = Path::new(&sound.path);
+	if !path.exists() {
+		if let Some(parent) = path.parent() {
+			std::fs::create_dir_all(parent)?;
+		}
+		backend(config).render(speech, path)?;
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+struct CountingBackend(std::cell::Cell<u32>);
+
+#[cfg(test)]
+impl TtsBackend for CountingBackend {
+	fn id(&self) -> String {
+		"counting".to_owned()
+	}
+
+	fn render(&self, speech: &Speech, output: &Path) -> Result<()> {
+		self.0.set(self.0.get() + 1);
+		std::fs::write(output, speech.text.as_bytes())?;
+		Ok(())
+	}
+}
+
+#[test]
+fn test_render_cached_reuses_rendered_speech() {
+	let cache_dir = std::env::temp_dir().join(format!("soundboard-tts-test-{}", std::process::id()));
+	let backend = CountingBackend(std::cell::Cell::new(0));
+	let speech = Speech {
+		text: "Hello".to_owned(),
+		..Speech::default()
+	};
+
+	let first = render_cached(&backend, &speech, &cache_dir).unwrap();
+	let second = render_cached(&backend, &speech, &cache_dir).unwrap();
+	assert_eq!(first, second);
+	assert_eq!(backend.0.get(), 1);
+
+	let other = Speech {
+		rate: speech.rate + 20,
+		..speech.clone()
+	};
+	assert_ne!(render_cached(&backend, &other, &cache_dir).unwrap(), first);
+	assert_eq!(backend.0.get(), 2);
+
+	let _ = std::fs::remove_dir_all(&cache_dir);
+}
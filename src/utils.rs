@@ -1,4 +1,10 @@
-use std::path::PathBuf;
+use std::{
+	fs::File,
+	io::Read,
+	path::{ Path, PathBuf },
+};
+
+use sha2::{ Digest, Sha256 };
 
 use winapi::um::winuser;
 
@@ -43,3 +49,61 @@ pub fn path_is_child(parent: &PathBuf, child: &PathBuf) -> std::io::Result<bool>
 	let child = child.canonicalize()?;
 	Ok(child.starts_with(parent))
 }
+
+// Hex encoded SHA-256 of the file contents
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+	let mut file = File::open(path)?;
+	let mut hasher = Sha256::new();
+	let mut buf = vec![0; 64 * 1024];
+	loop {
+		let len = file.read(&mut buf)?;
+		if len == 0 {
+			break;
+		}
+		hasher.update(&buf[..len]);
+	}
+	Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> String {
+	format!("{:x}", Sha256::digest(bytes))
+}
+
+// `stem.extension` in `dir`, with a number appended to the stem if a file already has that name
+pub fn unique_path(dir: &Path, stem: &str, extension: &str) -> PathBuf {
+	let mut path = dir.join(format!("{}.{}", stem, extension));
+	let mut idx = 1;
+	while path.exists() {
+		path = dir.join(format!("{}-{}.{}", stem, idx, extension));
+		idx += 1;
+	}
+	path
+}
+
+// Recursively collects the files accepted by `matches`, unreadable subfolders are skipped
+pub fn find_files(dir: &Path, matches: &dyn Fn(&Path) -> bool, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+	for path in std::fs::read_dir(dir)?.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+		if path.is_dir() {
+			let _ = find_files(&path, matches, files);
+		} else if matches(&path) {
+			files.push(path);
+		}
+	}
+	Ok(())
+}
+
+#[test]
+fn test_unique_path_skips_existing_files() {
+	let dir = std::env::temp_dir().join(format!("soundboard-unique-path-test-{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+
+	let first = unique_path(&dir, "recording", "wav");
+	assert_eq!(first, dir.join("recording.wav"));
+	std::fs::write(&first, b"").unwrap();
+	let second = unique_path(&dir, "recording", "wav");
+	assert_eq!(second, dir.join("recording-1.wav"));
+	std::fs::write(&second, b"").unwrap();
+	assert_eq!(unique_path(&dir, "recording", "wav"), dir.join("recording-2.wav"));
+
+	let _ = std::fs::remove_dir_all(&dir);
+}
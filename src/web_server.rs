@@ -1,6 +1,14 @@
+use std::sync::Arc;
+
 use anyhow::Result;
-use actix_files::Files;
-use actix_web::HttpServer;
+use actix_files::{ Files, NamedFile };
+use actix_web::{ web, HttpServer };
+
+async fn thumbnail(id: web::Path<String>) -> actix_web::Result<NamedFile> {
+	let dir = crate::images::cache_directory().map_err(actix_web::error::ErrorInternalServerError)?;
+	let path = crate::images::thumbnail_path(&dir, &id).ok_or_else(|| actix_web::error::ErrorNotFound("Unknown image"))?;
+	Ok(NamedFile::open(path)?)
+}
 
 #[derive(Default)]
 pub struct WebServer {
@@ -8,12 +16,19 @@ pub struct WebServer {
 }
 
 impl WebServer {
-	pub fn start(&mut self) -> Result<()> {
+	pub fn start(&mut self, app: Arc<crate::App>) -> Result<()> {
 		let address = "127.0.0.1:29815";
 		let path = crate::App::get_web_resources_directory().expect("Could not get web resources directory");
+		let images_path = crate::images::cache_directory()?;
 
 		self.server = Some(HttpServer::new(move || {
-			actix_web::App::new().service(Files::new("/", path.clone()))
+			// Registered before the web resources, which are served from the root
+			actix_web::App::new()
+				.app_data(web::Data::from(app.clone()))
+				.configure(crate::api::configure)
+				.route("/thumbnails/{id}", web::get().to(thumbnail))
+				.service(Files::new("/images", images_path.clone()))
+				.service(Files::new("/", path.clone()))
 		})
 		.workers(3)
 		.bind(address)?
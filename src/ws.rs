@@ -1,11 +1,19 @@
 use crate::audio;
-use crate::config::Sound;
+use crate::library;
+use crate::history::{ History, Snapshot };
+use crate::search::{ self, SearchQuery };
+use crate::stats::PlaySource;
+use crate::rate_limit::{ RateLimiter, Blocked, BlockReason };
+use crate::device_monitor::{ DeviceChanges, flag_missing_devices };
+use crate::config::{ Config, Sound, SoundImage, Speech, SoundGroup, OutputDevice, DeviceId, PluginChainConfig, PluginConfig, FolderBoard, Schedule, ReplayConfig, TtsConfig };
+use crate::sound_groups::GroupPicker;
+use crate::shortcut::GlobalShortcut;
+use crate::folder_sync::{ self, FolderFile };
 
 use std::{
-	thread,
-	time::Duration,
+	time::{ Duration, Instant },
 	net::SocketAddr,
-	collections::HashMap,
+	collections::{ HashMap, HashSet },
 	path::{ PathBuf, Path },
 	sync::{
 		Arc, Mutex,
@@ -15,7 +23,6 @@ use std::{
 
 use anyhow::Result;
 use serde_json::json;
-use rodio::DeviceTrait;
 use tokio::net::{ TcpListener, TcpStream };
 use tokio_tungstenite::tungstenite::Message;
 use futures_channel::mpsc::{ unbounded, UnboundedSender };
@@ -25,6 +32,14 @@ struct Client {
 	pub client_type: ClientType,
 	pub addr: SocketAddr,
 	pub tx: Tx,
+	pub levels_subscribed: bool,
+}
+
+// What changed when the references to deleted sounds were removed
+#[derive(Default)]
+struct RemovedReferences {
+	groups: bool,
+	schedules: bool,
 }
 
 #[derive(PartialEq, Eq)]
@@ -53,6 +68,13 @@ type PeerMap = HashMap<SocketAddr, Client>;
 pub struct WebSocketServer {
 	app: Option<Arc<crate::App>>,
 	peers: PeerMap,
+	group_picker: Mutex<GroupPicker>,
+	preview: Mutex<Option<audio::PlaybackHandle>>,
+	playbacks: audio::Playbacks,
+	recording: Mutex<Option<crate::recorder::Recording>>,
+	history: Mutex<History>,
+	rate_limiter: Mutex<RateLimiter>,
+	replay_shortcut: Mutex<Option<GlobalShortcut>>,
 	thread_handle: Option<std::thread::JoinHandle<()>>,
 	cancellation_token: Option<tokio_util::sync::CancellationToken>,
 }
@@ -73,6 +95,7 @@ impl WebSocketServer {
 				client_type: ClientType::Unknown,
 				addr,
 				tx,
+				levels_subscribed: false,
 			};
 			let mut instance = instance.lock().unwrap();
 			instance.send_theme(&client, instance.get_current_theme_name()).unwrap();
@@ -93,20 +116,57 @@ impl WebSocketServer {
 						let mut client = instance.peers.get_mut(&addr).unwrap();
 						let client_type: String = serde_json::from_value(data["clientType"].clone()).unwrap();
 						client.client_type = ClientType::parse(&client_type);
+					} else if msg_type == "subscribeLevels" || msg_type == "unsubscribeLevels" {
+						let mut instance = instance.lock().unwrap();
+						let mut client = instance.peers.get_mut(&addr).unwrap();
+						client.levels_subscribed = msg_type == "subscribeLevels";
 					}
 
 					let instance = instance.lock().unwrap();
 					let client = instance.peers.get(&addr).unwrap();
 					let res = match msg_type.as_ref() {
 						"sounds" => instance.sounds(&client),
+						"searchSounds" => instance.search_sounds(&client, &data),
+						"stats" => instance.stats(&client),
+						"recentlyPlayed" => instance.recently_played(&client),
 						"playSound" => instance.play_sound(&data),
+						"playGroup" => instance.play_group(&data),
+						"previewSound" => instance.preview_sound(&data),
+						"stopPreview" => instance.stop_preview(),
+						"soundGroups" => instance.sound_groups(&client),
+						"setSoundGroups" => instance.set_sound_groups(&data),
+						"setFolderBoards" => instance.set_folder_boards(&data),
+						"listSchedules" => instance.list_schedules(&client),
+						"addSchedule" => instance.add_schedule(&client, &data),
+						"removeSchedule" => instance.remove_schedule(&data),
 						"outputDevices" => instance.output_devices(&client),
+						"inputDevices" => instance.input_devices(&client),
 						"config" => instance.config(&client),
+						"setGlobalVolume" => instance.set_global_volume(&data),
 						"setOutputDevices" => instance.set_output_devices(&data),
+						"setCueOutputDevice" => instance.set_cue_output_device(&data),
+						"setLimiter" => instance.set_limiter(&data),
+						"outputLevels" => instance.output_levels(&client),
+						"plugins" => instance.plugins(&client),
+						"setPluginFolders" => instance.set_plugin_folders(&data),
+						"setPluginChain" => instance.set_plugin_chain(&data),
+						"pluginParameters" => instance.plugin_parameters(&client, &data),
+						"setPluginParameter" => instance.set_plugin_parameter(&data),
 						"setCopySound" => instance.set_copy_sound(&data),
 						"deleteSound" => instance.delete_sound(&client, &data),
 						"editSound" => instance.edit_sound(&client, &data),
+						"undo" => instance.undo(false),
+						"redo" => instance.undo(true),
+						"history" => instance.history(&client),
+						"missingFiles" => instance.missing_files(&client),
+						"relinkSounds" => instance.relink_sounds(&client, &data),
 						"addSounds" => instance.add_sounds(&data),
+						"addTtsSound" => instance.add_tts_sound(&data),
+						"addSoundFromUrl" => instance.add_sound_from_url(&client, &data),
+						"startRecording" => instance.start_recording(&data),
+						"stopRecording" => instance.stop_recording(),
+						"saveReplay" => instance.save_replay(data["seconds"].as_u64().map(|seconds| seconds as u32)),
+						"setReplay" => instance.set_replay(&data),
 						"addSoundsDialog" => instance.add_sounds_dialog(&client),
 						"browseSoundDialog" => instance.browse_sound_dialog(&client, &data),
 						"closeSoundboard" => instance.close_soundboard(),
@@ -115,7 +175,7 @@ impl WebSocketServer {
 						"isAutostartEnabled" => instance.is_autostart_enabled(&client),
 						"setAutostart" => instance.set_autostart(&data),
 						"currentVersion" => instance.current_version(&client),
-						"clientType" => Ok(()),
+						"clientType" | "subscribeLevels" | "unsubscribeLevels" => Ok(()),
 						_ => instance.error(&client, format!("Invalid message type {}", msg_type)),
 					};
 					if let Err(err) = res {
@@ -156,6 +216,15 @@ impl WebSocketServer {
 			instance.cancellation_token = Some(token.clone());
 		}
 
+		let levels_instance = instance.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Self::levels_interval());
+			loop {
+				interval.tick().await;
+				levels_instance.lock().unwrap().send_levels();
+			}
+		});
+
 		let join_handle = tokio::spawn(async move {
 			tokio::select! {
 				_ = token.cancelled() => {
@@ -183,6 +252,35 @@ impl WebSocketServer {
 		Ok(())
 	}
 
+	#[cfg(test)]
+	pub fn set_app(&mut self, app: Arc<crate::App>) {
+		self.app = Some(app);
+	}
+
+	fn levels_interval() -> Duration {
+		Duration::from_millis(50)
+	}
+
+	fn send_levels(&self) {
+		let levels = self.app.as_ref().unwrap().output_buses.update_levels();
+		if !self.peers.values().any(|client| client.levels_subscribed) {
+			return;
+		}
+
+		let msg: Message = json!({
+			"message": "levels",
+			"levels": levels,
+		})
+		.to_string()
+		.into();
+
+		for client in self.peers.values().filter(|client| client.levels_subscribed) {
+			if let Err(err) = client.tx.unbounded_send(msg.clone()) {
+				log::error!("Error in send_levels: {}", err);
+			}
+		}
+	}
+
 	pub fn stop(&mut self) {
 		log::info!("Stopping WebSocket server...");
 
@@ -211,6 +309,126 @@ impl WebSocketServer {
 		}
 	}
 
+	pub fn recording_finished(&self, result: Result<PathBuf>) {
+		self.recording.lock().unwrap().take();
+		if let Err(err) = self.broadcast_recording_state(false) {
+			log::error!("Error in recording_finished: {}", err);
+		}
+
+		let res = result.and_then(|path| self.do_add_sounds(vec![path.to_string_lossy().into_owned()]));
+		if let Err(err) = res {
+			log::error!("Could not add the recorded sound: {}", err);
+		}
+	}
+
+	// Also called from the global shortcut
+	pub fn save_replay(&self, seconds: Option<u32>) -> Result<()> {
+		let stem = format!("replay-{}", chrono::Local::now().format("%Y%m%d-%H%M%S"));
+		let path = crate::utils::unique_path(&Self::sounds_directory()?, &stem, "wav");
+		self.app.as_ref().unwrap().replay.lock().unwrap().save(&path, seconds)?;
+		self.do_add_sounds(vec![path.to_string_lossy().into_owned()])
+	}
+
+	// Ctrl+Shift+R saves the replay, it is only taken from other applications while instant replay is enabled
+	pub fn update_replay_shortcut(&self, replay: &ReplayConfig) {
+		let mut shortcut = self.replay_shortcut.lock().unwrap();
+		// Dropping the previous shortcut releases it
+		*shortcut = None;
+		if !replay.enabled {
+			return;
+		}
+
+		let app = self.app.as_ref().unwrap().clone();
+		let res = GlobalShortcut::register(
+			(winapi::um::winuser::MOD_CONTROL | winapi::um::winuser::MOD_SHIFT) as u32, 'R' as u32,
+			move || {
+				if let Err(err) = app.websocket_server.lock().unwrap().save_replay(None) {
+					log::error!("Could not save instant replay: {}", err);
+				}
+			},
+		);
+		// Another application may already have taken the shortcut
+		match res {
+			Ok(registered) => *shortcut = Some(registered),
+			Err(err) => log::error!("Could not register the instant replay shortcut: {}", err),
+		}
+	}
+
+	// Called by the folder watcher with the files found in each folder board that could be scanned
+	pub fn sync_folder_boards(&self, folders: &[(String, Vec<FolderFile>)]) {
+		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
+		let before = Snapshot::library(&config);
+		let mut changed = false;
+		let mut library_changed = false;
+		let mut removed_references = RemovedReferences::default();
+		let mut messages = Vec::new();
+
+		for (folder, files) in folders {
+			let mut ignored = match config.folder_boards.iter().find(|board| board.path == *folder) {
+				Some(board) => board.ignored.clone(),
+				None => continue,
+			};
+			let changes = folder_sync::sync_folder(&mut config.sounds, folder, files, &mut ignored);
+			if changes.ignored_changed {
+				if let Some(board) = config.folder_boards.iter_mut().find(|board| board.path == *folder) {
+					board.ignored = ignored;
+				}
+			}
+			changed |= !changes.is_empty() || changes.hashes_changed || changes.ignored_changed;
+			library_changed |= !changes.is_empty();
+
+			if !changes.added.is_empty() {
+				log::info!("Added {} sounds from folder board {}", changes.added.len(), folder);
+				messages.push(json!({
+					"message": "addedSounds",
+					"sounds": &changes.added,
+				}));
+			}
+			for (previous_path, sound) in changes.renamed.iter() {
+				log::info!("Sound {} was renamed to {}", previous_path, sound.path);
+				messages.push(json!({
+					"message": "modifiedSound",
+					"id": sound.id,
+					"previousPath": previous_path,
+					"sound": sound,
+				}));
+			}
+			for sound in changes.removed.iter() {
+				log::info!("Sound {} was removed from folder board {}", sound.path, folder);
+				Self::remove_sound_references(&mut config, &sound.id, &mut removed_references);
+				messages.push(json!({
+					"message": "removedSound",
+					"id": sound.id,
+					"path": sound.path,
+				}));
+			}
+		}
+
+		if !changed {
+			return;
+		}
+		config.save();
+
+		// Hashes computed for the first time are not worth an undo step
+		let mut res = Ok(());
+		if library_changed {
+			res = self.record_history("syncFolderBoards", before);
+			self.forget_deleted_sounds(&config);
+		}
+		res = res.and_then(|_| self.broadcast_removed_references(&config, &removed_references));
+		for msg in messages {
+			res = res.and_then(|_| self.broadcast(None, msg.to_string().into(), Some(vec![
+				ClientType::SettingsWindow,
+				ClientType::SoundboardWindow,
+				ClientType::BrowserSettingsWindow,
+				ClientType::BrowserSoundboardWindow,
+			])));
+		}
+		if let Err(err) = res {
+			log::error!("Error in sync_folder_boards: {}", err);
+		}
+	}
+
 	pub fn settings_browse_sound_dialog_callback(&self, request_id: u32, new_path: &PathBuf) {
 		let msg = json!({
 			"message": "requestCallback",
@@ -238,7 +456,32 @@ impl WebSocketServer {
 		}
 	}
 
-	fn process_sound_path(path: &mut String, app_dir: Option<&PathBuf>) -> Result<()> {
+	pub fn send_output_devices_changed(&self, devices: &[DeviceId], changes: &DeviceChanges, config_devices: &[OutputDevice]) {
+		let msg = json!({
+			"message": "outputDevicesChanged",
+			"devices": devices,
+			"added": &changes.added,
+			"removed": &changes.removed,
+			"config": config_devices,
+		})
+		.to_string()
+		.into();
+
+		if let Err(err) = self.broadcast(None, msg, Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow])) {
+			log::error!("Error in send_output_devices_changed: {}", err);
+		}
+	}
+
+	fn sounds_directory() -> Result<PathBuf> {
+		let mut path = crate::App::get_working_directory()?;
+		path.push("sounds");
+		if !path.exists() {
+			std::fs::create_dir(&path)?;
+		}
+		Ok(path)
+	}
+
+	pub fn process_sound_path(path: &mut String, app_dir: Option<&PathBuf>) -> Result<()> {
 		let app_dir = match app_dir {
 			Some(dir) => dir.clone(),
 			None => crate::App::get_working_directory()?,
@@ -269,39 +512,19 @@ impl WebSocketServer {
 		Ok(())
 	}
 
-	fn play_sound(&self, data: &serde_json::Value) -> Result<()> {
+	fn search_sounds(&self, client: &Client, data: &serde_json::Value) -> Result<()> {
+		// Without a query, the first page of the library is returned
+		let query: Option<SearchQuery> = serde_json::from_value(data["query"].clone())?;
+		let query = query.unwrap_or_default();
 		let config = self.app.as_ref().unwrap().config.lock().unwrap();
-		let sound_path: String = serde_json::from_value(data["sound"].clone())?;
-		let sound = config.sounds.iter().find(|sound| sound.path == sound_path);
-		if let Some(sound) = sound {
-			for dev in config.output_devices.clone() {
-				let sound_path = sound_path.clone();
-				let volume = config.global_volume * dev.volume * sound.volume;
-				thread::spawn(move || {
-					match audio::play_sound(sound_path.as_ref(), dev.name.as_ref()) {
-						Ok(res) => {
-							res.sound.set_volume(volume);
-							res.sound.sleep_until_end();
-						},
-						Err(err) => log::error!("Could not play sound: {}", err),
-					}
-				});
-			}
-		}
-
-		Ok(())
-	}
-
-	fn output_devices(&self, client: &Client) -> Result<()> {
-		let devices: Vec<String> = audio::get_output_devices()?
-			.iter()
-			.map(|dev| dev.name().unwrap())
-			.collect();
+		let stats = self.app.as_ref().unwrap().stats.lock().unwrap();
+		let results = search::search(&config.sounds, &query, |sound| stats.play_count(&sound.id));
 
 		client.tx.unbounded_send(
 			json!({
-				"message": "outputDevices",
-				"devices": devices,
+				"message": "searchResults",
+				"requestId": data["requestId"],
+				"results": results,
 			})
 			.to_string()
 			.into()
@@ -310,13 +533,12 @@ impl WebSocketServer {
 		Ok(())
 	}
 
-	fn config(&self, client: &Client) -> Result<()> {
-		let config = &self.app.as_ref().unwrap().config;
-
+	fn stats(&self, client: &Client) -> Result<()> {
+		let stats = self.app.as_ref().unwrap().stats.lock().unwrap();
 		client.tx.unbounded_send(
 			json!({
-				"message": "config",
-				"config": &config,
+				"message": "stats",
+				"stats": stats.sounds(),
 			})
 			.to_string()
 			.into()
@@ -325,145 +547,338 @@ impl WebSocketServer {
 		Ok(())
 	}
 
-	fn set_output_devices(&self, data: &serde_json::Value) -> Result<()> {
-		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
-		config.output_devices = serde_json::from_value(data["devices"].clone())?;
-		config.save();
-
-		let msg = json!({
-			"message": "configOutputDevices",
-			"config": &config.output_devices,
+	fn recently_played_message(&self, config: &Config) -> Message {
+		let stats = self.app.as_ref().unwrap().stats.lock().unwrap();
+		// Sounds that were deleted since are left out
+		let sounds: Vec<&Sound> = stats
+			.recently_played(10)
+			.into_iter()
+			.filter_map(|id| config.sounds.iter().find(|sound| sound.id == id))
+			.collect();
+		json!({
+			"message": "recentlyPlayed",
+			"sounds": sounds,
 		})
 		.to_string()
-		.into();
-		self.broadcast(None, msg, Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow]))?;
+		.into()
+	}
 
+	fn recently_played(&self, client: &Client) -> Result<()> {
+		let config = self.app.as_ref().unwrap().config.lock().unwrap();
+		client.tx.unbounded_send(self.recently_played_message(&config))?;
 		Ok(())
 	}
 
-	fn set_copy_sound(&self, data: &serde_json::Value) -> Result<()> {
-		let enabled: bool = serde_json::from_value(data["enabled"].clone())?;
-		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
-		config.sounds_config.copy_file = enabled;
-		config.save();
+	fn broadcast_recently_played(&self, config: &Config) -> Result<()> {
+		let msg = self.recently_played_message(config);
+		self.broadcast(None, msg, Some(vec![ClientType::SoundboardWindow, ClientType::BrowserSoundboardWindow]))
+	}
 
-		let msg = json!({
-			"message": "configSoundsConfig",
-			"config": &config.sounds_config,
-		})
-		.to_string()
-		.into();
-		self.broadcast(None, msg, Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow]))?;
+	fn play_sound(&self, data: &serde_json::Value) -> Result<()> {
+		let sound_id: String = serde_json::from_value(data["sound"].clone())?;
+		// Clients say when a sound was played with the keyboard
+		let source: Option<PlaySource> = serde_json::from_value(data["source"].clone())?;
+		// Blocked plays are reported to the soundboards by soundOnCooldown
+		let _ = self.play_sound_by_id(&sound_id, source.unwrap_or(PlaySource::Click));
 
 		Ok(())
 	}
 
-	fn delete_sound(&self, client: &Client, data: &serde_json::Value) -> Result<()> {
-		let sound_path: String = serde_json::from_value(data["sound"].clone())?;
-		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
-		config.sounds.retain(|sound| sound.path != sound_path);
-		config.save();
+	// Also used by the REST API, returns false if there is no such sound
+	pub fn play_sound_by_id(&self, sound_id: &str, source: PlaySource) -> Result<bool, Blocked> {
+		let config = self.app.as_ref().unwrap().config.lock().unwrap();
+		match config.find_sound(sound_id) {
+			Some(sound) => {
+				self.play_sound_on_output_devices(&config, sound, source)?;
+				Ok(true)
+			},
+			None => Ok(false),
+		}
+	}
 
-		let msg = json!({
-			"message": "removedSound",
-			"path": sound_path,
-		}).to_string().into();
-		self.broadcast(Some(client), msg, Some(vec![
-			ClientType::SettingsWindow,
-			ClientType::SoundboardWindow,
-			ClientType::BrowserSettingsWindow,
-			ClientType::BrowserSoundboardWindow,
-		]))?;
+	// Every play except previews goes through here, so cooldowns and the rate limit are enforced in one place
+	fn play_sound_on_output_devices(&self, config: &Config, sound: &Sound, source: PlaySource) -> Result<(), Blocked> {
+		// Checked first so that a play that cannot happen does not count against the limits
+		// Speech is rendered when it is added or edited, never on the play path
+		if !Path::new(&sound.path).exists() {
+			log::error!("Could not play sound {}: {} does not exist", sound.name, sound.path);
+			return Ok(());
+		}
+		let cooldown = sound.cooldown_ms.map(Duration::from_millis);
+		let allowed = self.rate_limiter
+			.lock().unwrap()
+			.try_play(&sound.id, cooldown, &config.rate_limit, Instant::now());
+		if let Err(blocked) = allowed {
+			if let Err(err) = self.broadcast_sound_on_cooldown(sound, &blocked) {
+				log::error!("Error in broadcast_sound_on_cooldown: {}", err);
+			}
+			return Err(blocked);
+		}
+		if let Some(cooldown) = cooldown {
+			// Lets the soundboards grey the sound out as soon as its cooldown starts
+			let started = Blocked {
+				reason: BlockReason::Cooldown,
+				remaining: cooldown,
+			};
+			if let Err(err) = self.broadcast_sound_on_cooldown(sound, &started) {
+				log::error!("Error in broadcast_sound_on_cooldown: {}", err);
+			}
+		}
+
+		let buses = &self.app.as_ref().unwrap().output_buses;
+		let devices = config.output_devices
+			.iter()
+			.filter(|dev| !dev.missing && sound.plays_on_device(&dev.id()));
+		self.app.as_ref().unwrap().stats.lock().unwrap().record(&sound.id, source);
+		if let Err(err) = self.broadcast_recently_played(config) {
+			log::error!("Error in broadcast_recently_played: {}", err);
+		}
+		for dev in devices {
+			let handle = audio::PlaybackHandle::new(config.global_volume * dev.volume * sound.volume);
+			if let Err(err) = audio::play_sound(buses, sound, &dev.id(), handle.clone()) {
+				log::error!("Could not play sound: {}", err);
+				continue;
+			}
+			self.playbacks.register(audio::ActivePlayback {
+				sound_id: sound.id.clone(),
+				device: dev.id(),
+				preview: false,
+				handle,
+			});
+		}
 
 		Ok(())
 	}
 
-	fn edit_sound(&self, client: &Client, data: &serde_json::Value) -> Result<()> {
-		let mut sound_edited: Sound = serde_json::from_value(data["sound"].clone())?;
-		let receive_update: Option<bool> = serde_json::from_value(data["receiveUpdate"].clone())?;
-		let prev_path: Result<String, serde_json::Error> = serde_json::from_value(data["previousPath"].clone());
-		let prev_path = match prev_path {
-			Ok(prev_path) => prev_path, // Use the previous path if any (which means the path has been modified)
-			Err(_) => sound_edited.path.clone(), // If the previous path is not specified, use the one in the sound struct
+	fn broadcast_sound_on_cooldown(&self, sound: &Sound, blocked: &Blocked) -> Result<()> {
+		self.broadcast(
+			None,
+			json!({
+				"message": "soundOnCooldown",
+				"id": &sound.id,
+				"reason": blocked.reason,
+				"remainingMs": blocked.remaining.as_millis() as u64,
+			})
+			.to_string()
+			.into(),
+			Some(vec![ClientType::SoundboardWindow, ClientType::BrowserSoundboardWindow])
+		)
+	}
+
+	fn update_playback_volumes(&self, config: &Config) {
+		self.playbacks.for_each(|playback| {
+			let sound = match config.sounds.iter().find(|sound| sound.id == playback.sound_id) {
+				Some(sound) => sound,
+				None => return,
+			};
+			let volume = if playback.preview {
+				config.cue_output_device
+					.as_ref()
+					.map(|dev| dev.volume * sound.volume)
+			} else {
+				config.output_devices
+					.iter()
+					.find(|dev| dev.id().matches(&playback.device))
+					.map(|dev| config.global_volume * dev.volume * sound.volume)
+			};
+			if let Some(volume) = volume {
+				playback.handle.set_volume(volume);
+			}
+		});
+	}
+
+	fn preview_sound(&self, data: &serde_json::Value) -> Result<()> {
+		let config = self.app.as_ref().unwrap().config.lock().unwrap();
+		let cue_device = match &config.cue_output_device {
+			Some(dev) if !dev.missing => dev.clone(),
+			Some(dev) => return Err(anyhow::Error::msg(format!("Cue output device {} is missing", dev.id()))),
+			None => return Err(anyhow::Error::msg("No cue output device configured")),
+		};
+		// The settings window sends the sound being edited so that unsaved changes can be previewed
+		let mut sound: Sound = match &data["sound"] {
+			serde_json::Value::String(sound_id) => match config.find_sound(sound_id) {
+				Some(sound) => sound.clone(),
+				None => return Ok(()),
+			},
+			sound => serde_json::from_value(sound.clone())?,
 		};
+		let tts_config = config.tts_config.clone();
+		drop(config);
+		if let Some(speech) = &sound.tts {
+			sound.path = Self::render_speech(&tts_config, speech)?;
+		}
 
-		Self::process_sound_path(&mut sound_edited.path, None)?;
+		self.stop_preview()?;
+
+		// Previews ignore the global volume since they never reach the public output devices
+		let buses = &self.app.as_ref().unwrap().output_buses;
+		let handle = audio::PlaybackHandle::new(cue_device.volume * sound.volume);
+		audio::play_sound(buses, &sound, &cue_device.id(), handle.clone())?;
+		*self.preview.lock().unwrap() = Some(handle.clone());
+		self.playbacks.register(audio::ActivePlayback {
+			sound_id: sound.id.clone(),
+			device: cue_device.id(),
+			preview: true,
+			handle,
+		});
 
-		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
-		let sound = config.sounds.iter_mut().find(|sound| sound.path == prev_path);
-		if let Some(sound) = sound {
-			let msg = json!({
-				"message": "modifiedSound",
-				"previousPath": prev_path,
-				"sound": sound_edited,
-			}).to_string().into();
+		Ok(())
+	}
 
-			*sound = sound_edited;
-			config.save();
+	fn stop_preview(&self) -> Result<()> {
+		if let Some(handle) = self.preview.lock().unwrap().take() {
+			handle.stop();
+		}
 
-			let except = match receive_update {
-				Some(true) => None,
-				_ => Some(client),
-			};
-			self.broadcast(except, msg, Some(vec![
-				ClientType::SettingsWindow,
-				ClientType::SoundboardWindow,
-				ClientType::BrowserSettingsWindow,
-				ClientType::BrowserSoundboardWindow,
-			]))?;
+		Ok(())
+	}
+
+	fn play_group(&self, data: &serde_json::Value) -> Result<()> {
+		let config = self.app.as_ref().unwrap().config.lock().unwrap();
+		let group_name: String = serde_json::from_value(data["group"].clone())?;
+		let group = config.sound_groups.iter().find(|group| group.name == group_name);
+		if let Some(group) = group {
+			let picked = self.group_picker.lock().unwrap().pick(group);
+			let sound = picked.and_then(|id| config.sounds.iter().find(|sound| sound.id == id));
+			if let Some(sound) = sound {
+				let _ = self.play_sound_on_output_devices(&config, sound, PlaySource::Group);
+			}
 		}
 
 		Ok(())
 	}
 
-	fn add_sounds(&self, data: &serde_json::Value) -> Result<()> {
-		self.do_add_sounds(serde_json::from_value(data["files"].clone())?)?;
+	fn sound_groups(&self, client: &Client) -> Result<()> {
+		let config = self.app.as_ref().unwrap().config.lock().unwrap();
+		client.tx.unbounded_send(
+			json!({
+				"message": "soundGroups",
+				"groups": &config.sound_groups,
+			})
+			.to_string()
+			.into()
+		)?;
+
 		Ok(())
 	}
 
-	fn do_add_sounds(&self, mut paths: Vec<String>) -> Result<()> {
+	fn set_sound_groups(&self, data: &serde_json::Value) -> Result<()> {
+		let mut groups: Vec<SoundGroup> = serde_json::from_value(data["groups"].clone())?;
 		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
-		let app_dir = crate::App::get_working_directory()?;
+		// Groups can only reference sounds that are in the library, paths sent by older clients are replaced by ids
+		for group in groups.iter_mut() {
+			group.sounds = group.sounds
+				.iter()
+				.filter_map(|sound| config.find_sound(sound).map(|sound| sound.id.clone()))
+				.collect();
+		}
+		let before = Snapshot::library(&config);
+		config.sound_groups = groups;
+		config.save();
 
-		if config.sounds_config.copy_file {
-			let mut sounds_dir = app_dir.clone();
-			sounds_dir.push("sounds");
-			if !sounds_dir.exists() {
-				std::fs::create_dir(&sounds_dir)?;
-			}
+		self.record_history("setSoundGroups", before)?;
+		self.broadcast_sound_groups(&config)
+	}
 
-			for path in paths.iter_mut() {
-				let path_buf = PathBuf::from(&path);
-				if !crate::utils::path_is_child(&sounds_dir, &path_buf)? {
-					let mut target_path = sounds_dir.clone();
-					target_path.push(path_buf.file_name().unwrap());
-					std::fs::copy(&path, &target_path)?;
-					*path = target_path.to_string_lossy().into_owned();
-				}
+	fn set_folder_boards(&self, data: &serde_json::Value) -> Result<()> {
+		let mut boards: Vec<FolderBoard> = serde_json::from_value(data["boards"].clone())?;
+		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
+		// The ignored files are kept track of here, clients cannot change them
+		for board in boards.iter_mut() {
+			board.ignored = config.folder_boards
+				.iter()
+				.find(|existing| existing.path == board.path)
+				.map(|existing| existing.ignored.clone())
+				.unwrap_or_default();
+		}
+		config.folder_boards = boards;
+
+		// Sounds of a board that was removed are kept as regular sounds
+		let before = Snapshot::library(&config);
+		let boards: Vec<FolderBoard> = config.folder_boards.clone();
+		let mut sounds_changed = false;
+		for sound in config.sounds.iter_mut() {
+			let board_exists = match &sound.folder {
+				Some(folder) => boards.iter().any(|board| board.path == *folder),
+				None => true,
+			};
+			if !board_exists {
+				sound.folder = None;
+				sounds_changed = true;
 			}
 		}
-
-		for path in paths.iter_mut() {
-			Self::process_sound_path(path, Some(&app_dir))?;
+		config.save();
+		if sounds_changed {
+			self.record_history("setFolderBoards", before)?;
 		}
 
-		paths.retain(|path| !config.sounds.iter().any(|sound| sound.path == *path));
-		let sounds: Vec<Sound> = paths
-			.iter()
-			.map(|path| Sound {
-				path: path.to_string(),
-				name: Path::new(&path).file_stem().unwrap().to_os_string().into_string().unwrap(),
-				volume: 1.0,
-				image: None,
-			})
-			.collect();
 		let msg = json!({
-			"message": "addedSounds",
-			"sounds": &sounds,
-		}).to_string().into();
-		config.sounds.extend(sounds);
+			"message": "configFolderBoards",
+			"config": &config.folder_boards,
+		})
+		.to_string()
+		.into();
+		self.broadcast(None, msg, Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow]))?;
+
+		Ok(())
+	}
+
+	fn schedules_message(config: &Config) -> Message {
+		json!({
+			"message": "schedules",
+			"schedules": &config.schedules,
+		})
+		.to_string()
+		.into()
+	}
+
+	fn list_schedules(&self, client: &Client) -> Result<()> {
+		let config = self.app.as_ref().unwrap().config.lock().unwrap();
+		client.tx.unbounded_send(Self::schedules_message(&config))?;
+
+		Ok(())
+	}
+
+	// Replaces the schedule with the same id if there is one, so that schedules can also be edited
+	fn add_schedule(&self, client: &Client, data: &serde_json::Value) -> Result<()> {
+		let mut schedule: Schedule = serde_json::from_value(data["schedule"].clone())?;
+		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
+		schedule.sound = match config.find_sound(&schedule.sound) {
+			Some(sound) => sound.id.clone(),
+			None => return self.error(client, format!("Unknown sound {}", schedule.sound)),
+		};
+		if let Err(err) = crate::scheduler::next_run(&schedule.rule, chrono::Local::now().naive_local()) {
+			return self.error(client, format!("Invalid schedule: {}", err));
+		}
+
+		match config.schedules.iter_mut().find(|existing| !schedule.id.is_empty() && existing.id == schedule.id) {
+			Some(existing) => *existing = schedule,
+			None => {
+				schedule.id = uuid::Uuid::new_v4().to_string();
+				config.schedules.push(schedule);
+			},
+		}
+		config.save();
+
+		self.broadcast(None, Self::schedules_message(&config), Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow]))
+	}
+
+	fn remove_schedule(&self, data: &serde_json::Value) -> Result<()> {
+		let id: String = serde_json::from_value(data["id"].clone())?;
+		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
+		config.schedules.retain(|schedule| schedule.id != id);
 		config.save();
 
+		self.broadcast(None, Self::schedules_message(&config), Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow]))
+	}
+
+	fn broadcast_sound_groups(&self, config: &Config) -> Result<()> {
+		let msg = json!({
+			"message": "configSoundGroups",
+			"config": &config.sound_groups,
+		})
+		.to_string()
+		.into();
 		self.broadcast(None, msg, Some(vec![
 			ClientType::SettingsWindow,
 			ClientType::SoundboardWindow,
@@ -474,6 +889,999 @@ impl WebSocketServer {
 		Ok(())
 	}
 
+	fn output_devices(&self, client: &Client) -> Result<()> {
+		let devices = audio::get_output_device_ids()?;
+
+		client.tx.unbounded_send(
+			json!({
+				"message": "outputDevices",
+				"devices": devices,
+			})
+			.to_string()
+			.into()
+		)?;
+
+		Ok(())
+	}
+
+	fn input_devices(&self, client: &Client) -> Result<()> {
+		let devices = audio::get_input_device_ids()?;
+
+		client.tx.unbounded_send(
+			json!({
+				"message": "inputDevices",
+				"devices": devices,
+			})
+			.to_string()
+			.into()
+		)?;
+
+		Ok(())
+	}
+
+	fn config(&self, client: &Client) -> Result<()> {
+		let config = &self.app.as_ref().unwrap().config;
+
+		client.tx.unbounded_send(
+			json!({
+				"message": "config",
+				"config": &config,
+			})
+			.to_string()
+			.into()
+		)?;
+
+		Ok(())
+	}
+
+	fn set_global_volume(&self, data: &serde_json::Value) -> Result<()> {
+		let volume: f32 = serde_json::from_value(data["volume"].clone())?;
+		// Sliders send every step without saving, then save once they are released
+		let save = data["save"].as_bool().unwrap_or(true);
+		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
+		config.global_volume = volume.max(0.0);
+		if save {
+			config.save();
+		}
+		self.update_playback_volumes(&config);
+
+		let msg = json!({
+			"message": "configGlobalVolume",
+			"config": config.global_volume,
+		})
+		.to_string()
+		.into();
+		self.broadcast(None, msg, Some(vec![
+			ClientType::SettingsWindow,
+			ClientType::SoundboardWindow,
+			ClientType::BrowserSettingsWindow,
+			ClientType::BrowserSoundboardWindow,
+		]))?;
+
+		Ok(())
+	}
+
+	fn set_output_devices(&self, data: &serde_json::Value) -> Result<()> {
+		let available = audio::get_output_device_ids()?;
+		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
+		let before = Snapshot::output_devices(&config);
+		config.output_devices = serde_json::from_value(data["devices"].clone())?;
+		flag_missing_devices(config.output_devices.iter_mut(), &available);
+		config.save();
+		self.update_playback_volumes(&config);
+		self.record_history("setOutputDevices", before)?;
+
+		let msg = json!({
+			"message": "configOutputDevices",
+			"config": &config.output_devices,
+		})
+		.to_string()
+		.into();
+		self.broadcast(None, msg, Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow]))?;
+
+		Ok(())
+	}
+
+	fn set_cue_output_device(&self, data: &serde_json::Value) -> Result<()> {
+		let available = audio::get_output_device_ids()?;
+		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
+		config.cue_output_device = serde_json::from_value(data["device"].clone())?;
+		flag_missing_devices(config.cue_output_device.iter_mut(), &available);
+		config.save();
+		self.update_playback_volumes(&config);
+
+		let msg = json!({
+			"message": "configCueOutputDevice",
+			"config": &config.cue_output_device,
+		})
+		.to_string()
+		.into();
+		self.broadcast(None, msg, Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow]))?;
+
+		Ok(())
+	}
+
+	fn set_limiter(&self, data: &serde_json::Value) -> Result<()> {
+		let app = self.app.as_ref().unwrap();
+		let mut config = app.config.lock().unwrap();
+		config.limiter = serde_json::from_value(data["limiter"].clone())?;
+		config.save();
+		app.output_buses.set_limiter(&config.limiter);
+
+		let msg = json!({
+			"message": "configLimiter",
+			"config": &config.limiter,
+		})
+		.to_string()
+		.into();
+		self.broadcast(None, msg, Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow]))?;
+
+		Ok(())
+	}
+
+	fn output_levels(&self, client: &Client) -> Result<()> {
+		let levels = self.app.as_ref().unwrap().output_buses.levels();
+
+		client.tx.unbounded_send(
+			json!({
+				"message": "outputLevels",
+				"levels": levels,
+			})
+			.to_string()
+			.into()
+		)?;
+
+		Ok(())
+	}
+
+	fn plugins(&self, client: &Client) -> Result<()> {
+		let folders = self.app.as_ref().unwrap().config.lock().unwrap().plugins_config.folders.clone();
+		let tx = client.tx.clone();
+
+		// Scanning loads every DLL in the folders, the server must not stay locked in the meantime
+		std::thread::spawn(move || {
+			let plugins = crate::plugins::scan(&folders);
+			let msg = json!({
+				"message": "plugins",
+				"plugins": plugins,
+			});
+			if let Err(err) = tx.unbounded_send(msg.to_string().into()) {
+				log::error!("Could not send the plugins: {}", err);
+			}
+		});
+
+		Ok(())
+	}
+
+	fn set_plugin_folders(&self, data: &serde_json::Value) -> Result<()> {
+		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
+		config.plugins_config.folders = serde_json::from_value(data["folders"].clone())?;
+		config.save();
+
+		let msg = json!({
+			"message": "configPluginsConfig",
+			"config": &config.plugins_config,
+		})
+		.to_string()
+		.into();
+		self.broadcast(None, msg, Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow]))?;
+
+		Ok(())
+	}
+
+	fn broadcast_plugin_chains(&self, config: &Config) -> Result<()> {
+		let msg = json!({
+			"message": "configPluginChains",
+			"config": &config.plugin_chains,
+		})
+		.to_string()
+		.into();
+		self.broadcast(None, msg, Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow]))
+	}
+
+	fn set_plugin_chain(&self, data: &serde_json::Value) -> Result<()> {
+		let device: DeviceId = serde_json::from_value(data["device"].clone())?;
+		let plugins: Vec<PluginConfig> = serde_json::from_value(data["plugins"].clone())?;
+		let app = self.app.as_ref().unwrap();
+		let mut config = app.config.lock().unwrap();
+
+		config.plugin_chains.retain(|chain| !chain.device.matches(&device));
+		if !plugins.is_empty() {
+			config.plugin_chains.push(PluginChainConfig {
+				device: device.clone(),
+				plugins: plugins.clone(),
+			});
+		}
+		config.save();
+		app.output_buses.set_plugins(&device, &plugins);
+
+		self.broadcast_plugin_chains(&config)
+	}
+
+	fn plugin_parameters(&self, client: &Client, data: &serde_json::Value) -> Result<()> {
+		let device: DeviceId = serde_json::from_value(data["device"].clone())?;
+		let slot: usize = serde_json::from_value(data["slot"].clone())?;
+		let plugin = self.app.as_ref().unwrap().output_buses.plugin(&device, slot)?;
+
+		client.tx.unbounded_send(
+			json!({
+				"message": "pluginParameters",
+				"device": device,
+				"slot": slot,
+				"parameters": plugin.parameters(),
+			})
+			.to_string()
+			.into()
+		)?;
+
+		Ok(())
+	}
+
+	fn set_plugin_parameter(&self, data: &serde_json::Value) -> Result<()> {
+		let device: DeviceId = serde_json::from_value(data["device"].clone())?;
+		let slot: usize = serde_json::from_value(data["slot"].clone())?;
+		let index: i32 = serde_json::from_value(data["index"].clone())?;
+		let value: f32 = serde_json::from_value(data["value"].clone())?;
+		let app = self.app.as_ref().unwrap();
+
+		let plugin = app.output_buses.plugin(&device, slot)?;
+		plugin.set_parameter(index, value)?;
+
+		// Changing a parameter can affect others, so the whole state of the plugin is saved
+		let mut config = app.config.lock().unwrap();
+		let plugin_config = config.plugin_chains
+			.iter_mut()
+			.find(|chain| chain.device.matches(&device))
+			.and_then(|chain| chain.plugins.get_mut(slot));
+		if let Some(plugin_config) = plugin_config {
+			plugin_config.parameters = plugin.values();
+			plugin_config.chunk = plugin.chunk();
+			config.save();
+		}
+
+		Ok(())
+	}
+
+	fn set_copy_sound(&self, data: &serde_json::Value) -> Result<()> {
+		let enabled: bool = serde_json::from_value(data["enabled"].clone())?;
+		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
+		config.sounds_config.copy_file = enabled;
+		config.save();
+
+		let msg = json!({
+			"message": "configSoundsConfig",
+			"config": &config.sounds_config,
+		})
+		.to_string()
+		.into();
+		self.broadcast(None, msg, Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow]))?;
+
+		Ok(())
+	}
+
+	fn delete_sound(&self, client: &Client, data: &serde_json::Value) -> Result<()> {
+		let sound_id: String = serde_json::from_value(data["sound"].clone())?;
+		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
+		let before = Snapshot::library(&config);
+		let sound = match config.sound_index(&sound_id) {
+			Some(idx) => config.sounds.remove(idx),
+			None => return Ok(()),
+		};
+		let mut removed_references = RemovedReferences::default();
+		Self::remove_sound_references(&mut config, &sound.id, &mut removed_references);
+		// Otherwise the sound of a folder board would come back on the next scan
+		if let Some(folder) = &sound.folder {
+			if let Some(board) = config.folder_boards.iter_mut().find(|board| board.path == *folder) {
+				board.ignored.push(sound.path.clone());
+			}
+		}
+		config.save();
+		self.record_history("deleteSound", before)?;
+		self.forget_deleted_sounds(&config);
+		self.broadcast_removed_references(&config, &removed_references)?;
+
+		let msg = json!({
+			"message": "removedSound",
+			"id": sound.id,
+			"path": sound.path,
+		}).to_string().into();
+		self.broadcast(Some(client), msg, Some(vec![
+			ClientType::SettingsWindow,
+			ClientType::SoundboardWindow,
+			ClientType::BrowserSettingsWindow,
+			ClientType::BrowserSoundboardWindow,
+		]))?;
+
+		Ok(())
+	}
+
+	fn edit_sound(&self, client: &Client, data: &serde_json::Value) -> Result<()> {
+		let sound_edited: Sound = serde_json::from_value(data["sound"].clone())?;
+		let receive_update: Option<bool> = serde_json::from_value(data["receiveUpdate"].clone())?;
+		let prev_path: Result<String, serde_json::Error> = serde_json::from_value(data["previousPath"].clone());
+		let sound_id = if !sound_edited.id.is_empty() {
+			sound_edited.id.clone()
+		} else {
+			// Clients that predate ids send the previous path if the path has been modified
+			match prev_path {
+				Ok(prev_path) => prev_path,
+				Err(_) => sound_edited.path.clone(),
+			}
+		};
+		let except = match receive_update {
+			Some(true) => None,
+			_ => Some(client),
+		};
+		self.do_edit_sound(&sound_id, sound_edited, except)?;
+
+		Ok(())
+	}
+
+	// Used by the REST API, every client is notified of the change
+	pub fn edit_sound_by_id(&self, sound_id: &str, sound_edited: Sound) -> Result<Option<Sound>> {
+		self.do_edit_sound(sound_id, sound_edited, None)
+	}
+
+	// Returns the sound as it was saved, or None if there is no such sound
+	fn do_edit_sound(&self, sound_id: &str, mut sound_edited: Sound, except: Option<&Client>) -> Result<Option<Sound>> {
+		Self::process_sound_path(&mut sound_edited.path, None)?;
+
+		if let Some(speech) = &sound_edited.tts {
+			// Editing the text or the voice renders the speech to a different file
+			let tts_config = self.app.as_ref().unwrap().config.lock().unwrap().tts_config.clone();
+			sound_edited.path = Self::render_speech(&tts_config, speech)?;
+		}
+		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
+		let (prev_path, prev_image) = match config.find_sound(sound_id) {
+			Some(sound) => {
+				sound_edited.id = sound.id.clone();
+				(sound.path.clone(), sound.image.clone())
+			},
+			None => return Ok(None),
+		};
+		// Clients send the whole sound on every edit, the image is only cached when it changes
+		let uncached_image = match &sound_edited.image {
+			Some(SoundImage::Cached(_)) | None => None,
+			Some(_) if sound_edited.image == prev_image => None,
+			Some(image) => Some((sound_edited.id.clone(), image.clone())),
+		};
+		if sound_edited.path != prev_path && sound_edited.folder.is_none() {
+			sound_edited.hash = crate::utils::hash_file(Path::new(&sound_edited.path)).ok();
+		}
+		let before = Snapshot::library(&config);
+		let saved = sound_edited.clone();
+		self.replace_sound(&mut config, sound_id, sound_edited, except)?;
+		config.save();
+		// The settings window sends an edit on every keystroke
+		self.history.lock().unwrap().record_merging("editSound", sound_id, before);
+		self.broadcast_history()?;
+		self.forget_deleted_sounds(&config);
+		drop(config);
+		if let Some(image) = uncached_image {
+			self.cache_images_in_background(vec![image], Self::image_cache_delay());
+		}
+
+		Ok(Some(saved))
+	}
+
+	// Replaces a sound and updates the volume of its playbacks, the caller saves the config
+	fn replace_sound(&self, config: &mut Config, sound_id: &str, mut sound_edited: Sound, except: Option<&Client>) -> Result<bool> {
+		let idx = match config.sound_index(sound_id) {
+			Some(idx) => idx,
+			None => return Ok(false),
+		};
+		let prev_path = config.sounds[idx].path.clone();
+		sound_edited.id = config.sounds[idx].id.clone();
+		let msg = json!({
+			"message": "modifiedSound",
+			"id": sound_edited.id,
+			"previousPath": prev_path,
+			"sound": sound_edited,
+		}).to_string().into();
+
+		config.sounds[idx] = sound_edited;
+		self.update_playback_volumes(config);
+
+		self.broadcast(except, msg, Some(vec![
+			ClientType::SettingsWindow,
+			ClientType::SoundboardWindow,
+			ClientType::BrowserSettingsWindow,
+			ClientType::BrowserSoundboardWindow,
+		]))?;
+
+		Ok(true)
+	}
+
+	fn record_history(&self, action: &str, before: Snapshot) -> Result<()> {
+		self.history.lock().unwrap().record(action, before);
+		self.broadcast_history()
+	}
+
+	fn history_message(&self) -> Message {
+		let history = self.history.lock().unwrap();
+		json!({
+			"message": "history",
+			"undo": history.undo_action(),
+			"redo": history.redo_action(),
+		})
+		.to_string()
+		.into()
+	}
+
+	fn broadcast_history(&self) -> Result<()> {
+		self.broadcast(None, self.history_message(), Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow]))
+	}
+
+	fn history(&self, client: &Client) -> Result<()> {
+		client.tx.unbounded_send(self.history_message())?;
+		Ok(())
+	}
+
+	fn undo(&self, redo: bool) -> Result<()> {
+		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
+		let mut history = self.history.lock().unwrap();
+		let replaced = if redo {
+			history.redo(&mut config)
+		} else {
+			history.undo(&mut config)
+		};
+		let library_changed = match replaced {
+			Some(Snapshot::Library { .. }) => true,
+			Some(Snapshot::OutputDevices(_)) => false,
+			None => return Ok(()),
+		};
+		drop(history);
+		config.save();
+		self.update_playback_volumes(&config);
+
+		if library_changed {
+			let msg = json!({
+				"message": "sounds",
+				"sounds": &config.sounds,
+			}).to_string().into();
+			self.broadcast(None, msg, Some(vec![
+				ClientType::SettingsWindow,
+				ClientType::SoundboardWindow,
+				ClientType::BrowserSettingsWindow,
+				ClientType::BrowserSoundboardWindow,
+			]))?;
+			self.broadcast_sound_groups(&config)?;
+			self.broadcast(None, Self::schedules_message(&config), Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow]))?;
+		} else {
+			let msg = json!({
+				"message": "configOutputDevices",
+				"config": &config.output_devices,
+			}).to_string().into();
+			self.broadcast(None, msg, Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow]))?;
+		}
+		self.broadcast_history()
+	}
+
+	fn missing_files(&self, client: &Client) -> Result<()> {
+		let config = self.app.as_ref().unwrap().config.lock().unwrap();
+
+		client.tx.unbounded_send(
+			json!({
+				"message": "missingFiles",
+				"sounds": library::find_missing_files(&config.sounds),
+			})
+			.to_string()
+			.into()
+		)?;
+
+		Ok(())
+	}
+
+	// Looks for the missing files of the given sounds (or of all sounds) in a folder and its subfolders
+	fn relink_sounds(&self, client: &Client, data: &serde_json::Value) -> Result<()> {
+		let folder: String = serde_json::from_value(data["folder"].clone())?;
+		let only: Option<Vec<String>> = serde_json::from_value(data["sounds"].clone())?;
+
+		let mut files = Vec::new();
+		crate::utils::find_files(Path::new(&folder), &|path| {
+			audio::is_supported_file(path) || library::is_image_file(path)
+		}, &mut files)?;
+		let hash_file = |path: &Path| crate::utils::hash_file(path).ok();
+		let mut relinker = library::Relinker::new(&files, &hash_file);
+
+		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
+		let app_dir = crate::App::get_working_directory()?;
+		let only: Option<Vec<String>> = only.map(|sounds| sounds
+			.iter()
+			.filter_map(|sound| config.find_sound(sound).map(|sound| sound.id.clone()))
+			.collect());
+		let missing: Vec<Sound> = library::find_missing_files(&config.sounds)
+			.into_iter()
+			.filter(|missing| match &only {
+				Some(ids) => ids.contains(&missing.id),
+				None => true,
+			})
+			.filter_map(|missing| config.find_sound(&missing.id).cloned())
+			.collect();
+
+		let before = Snapshot::library(&config);
+		let mut relinked = Vec::new();
+		let mut unresolved = Vec::new();
+		for sound in missing {
+			let mut sound_relinked = match relinker.relink(&sound) {
+				Some(sound_relinked) => sound_relinked,
+				None => {
+					unresolved.push(sound.id);
+					continue;
+				},
+			};
+			Self::process_sound_path(&mut sound_relinked.path, Some(&app_dir))?;
+			if let Some(SoundImage::File(image)) = &mut sound_relinked.image {
+				Self::process_sound_path(image, Some(&app_dir))?;
+			}
+			let path = sound_relinked.path.clone();
+			if self.replace_sound(&mut config, &sound.id, sound_relinked, None)? {
+				relinked.push(json!({
+					"id": sound.id,
+					"previousPath": sound.path,
+					"path": path,
+				}));
+			}
+		}
+		if !relinked.is_empty() {
+			config.save();
+			self.record_history("relinkSounds", before)?;
+		}
+
+		client.tx.unbounded_send(
+			json!({
+				"message": "relinkResult",
+				"relinked": relinked,
+				"unresolved": unresolved,
+			})
+			.to_string()
+			.into()
+		)?;
+
+		Ok(())
+	}
+
+	fn add_sounds(&self, data: &serde_json::Value) -> Result<()> {
+		self.do_add_sounds(serde_json::from_value(data["files"].clone())?)?;
+		Ok(())
+	}
+
+	fn do_add_sounds(&self, paths: Vec<String>) -> Result<()> {
+		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
+		let app_dir = crate::App::get_working_directory()?;
+		let sounds_directory = Self::sounds_directory()?;
+		let sounds_dir = if config.sounds_config.copy_file {
+			Some(sounds_directory.clone())
+		} else {
+			None
+		};
+
+		// Sounds added before hashes were kept are hashed once, so that duplicates can be found
+		for sound in config.sounds.iter_mut().filter(|sound| sound.hash.is_none()) {
+			sound.hash = crate::utils::hash_file(Path::new(&sound.path)).ok();
+		}
+
+		let before = Snapshot::library(&config);
+		let mut sounds: Vec<Sound> = Vec::new();
+		let mut duplicates = Vec::new();
+		for path in paths {
+			let hash = crate::utils::hash_file(Path::new(&path))?;
+			let existing = config.sounds
+				.iter()
+				.chain(sounds.iter())
+				.find(|sound| sound.hash.as_ref() == Some(&hash));
+			if let Some(existing) = existing {
+				duplicates.push(json!({
+					"path": path,
+					"existing": {
+						"id": existing.id,
+						"name": existing.name,
+						"path": existing.path,
+					},
+				}));
+				// Downloads, recordings and replays are written to the sounds directory before being added,
+				// the library does not collect them so a duplicate one is deleted here
+				let file = Path::new(&path);
+				let referenced = config.sounds.iter().any(|sound| app_dir.join(&sound.path) == file);
+				if file.parent() == Some(sounds_directory.as_path()) && !referenced {
+					if let Err(err) = std::fs::remove_file(file) {
+						log::error!("Could not remove duplicate sound file {}: {}", file.display(), err);
+					}
+				}
+				continue;
+			}
+
+			let name = Path::new(&path).file_stem().unwrap().to_string_lossy().into_owned();
+			let mut path = match &sounds_dir {
+				Some(dir) => library::store_file(dir, Path::new(&path), &hash)?.to_string_lossy().into_owned(),
+				None => path,
+			};
+			let image = Self::cover_art(Path::new(&path));
+			Self::process_sound_path(&mut path, Some(&app_dir))?;
+			sounds.push(Sound {
+				image,
+				hash: Some(hash),
+				..Sound::new(name, path)
+			});
+		}
+
+		if !sounds.is_empty() {
+			self.record_history("addSounds", before)?;
+		}
+		let msg = json!({
+			"message": "addedSounds",
+			"sounds": &sounds,
+		}).to_string().into();
+		config.sounds.extend(sounds);
+		config.save();
+
+		self.broadcast(None, msg, Some(vec![
+			ClientType::SettingsWindow,
+			ClientType::SoundboardWindow,
+			ClientType::BrowserSettingsWindow,
+			ClientType::BrowserSoundboardWindow,
+		]))?;
+
+		if !duplicates.is_empty() {
+			let msg = json!({
+				"message": "duplicateSounds",
+				"duplicates": duplicates,
+			}).to_string().into();
+			self.broadcast(None, msg, Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow]))?;
+		}
+
+		Ok(())
+	}
+
+	// Falls back to the original image if it cannot be cached, clients can still load it themselves
+	fn cache_image(image: &SoundImage) -> SoundImage {
+		let cached = crate::images::cache_directory().and_then(|dir| crate::images::cache_image(&dir, image));
+		match cached {
+			Ok(cached) => cached,
+			Err(err) => {
+				log::warn!("Could not cache the image {:?}: {}", image, err);
+				image.clone()
+			},
+		}
+	}
+
+	// Long enough for an image URL being typed to be complete
+	fn image_cache_delay() -> Duration {
+		Duration::from_secs(1)
+	}
+
+	// Downloads and copies can be slow, so images are cached without holding the server lock.
+	// A sound only switches to the cached image if it still uses the same image by then
+	fn cache_images_in_background(&self, images: Vec<(String, SoundImage)>, delay: Duration) {
+		if images.is_empty() {
+			return;
+		}
+		let app = self.app.clone().unwrap();
+
+		std::thread::spawn(move || {
+			std::thread::sleep(delay);
+			for (sound_id, image) in images {
+				let still_used = match app.config.lock().unwrap().find_sound(&sound_id) {
+					Some(sound) => sound.image.as_ref() == Some(&image),
+					None => false,
+				};
+				if !still_used {
+					continue;
+				}
+				let cached = Self::cache_image(&image);
+				if cached == image {
+					continue;
+				}
+				if let Err(err) = app.websocket_server.lock().unwrap().set_cached_image(&sound_id, &image, cached) {
+					log::error!("Error in set_cached_image: {}", err);
+				}
+			}
+		});
+	}
+
+	fn set_cached_image(&self, sound_id: &str, image: &SoundImage, cached: SoundImage) -> Result<()> {
+		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
+		let sound = config.sounds
+			.iter_mut()
+			.find(|sound| sound.id == sound_id && sound.image.as_ref() == Some(image));
+		let sound = match sound {
+			Some(sound) => sound,
+			None => return Ok(()),
+		};
+		sound.image = Some(cached.clone());
+		let msg = json!({
+			"message": "modifiedSound",
+			"id": sound_id,
+			"previousPath": &sound.path,
+			"sound": &sound,
+		}).to_string().into();
+		config.save();
+
+		self.broadcast(None, msg, Some(vec![ClientType::SoundboardWindow, ClientType::BrowserSoundboardWindow]))?;
+		// Only the image is replaced in the settings windows, so that the fields being edited keep their focus
+		let msg = json!({
+			"message": "soundImageCached",
+			"id": sound_id,
+			"image": cached,
+		}).to_string().into();
+		self.broadcast(None, msg, Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow]))
+	}
+
+	// Moves the images of the sounds added before the image cache existed into it
+	pub fn cache_sound_images(&self) {
+		let config = self.app.as_ref().unwrap().config.lock().unwrap();
+		let images = config.sounds
+			.iter()
+			.filter_map(|sound| match &sound.image {
+				Some(SoundImage::Cached(_)) | None => None,
+				Some(image) => Some((sound.id.clone(), image.clone())),
+			})
+			.collect();
+		drop(config);
+		self.cache_images_in_background(images, Duration::from_secs(0));
+	}
+
+	// Speech whose file was removed from the cache is rendered again, away from the play path
+	pub fn render_missing_speech(&self) {
+		let config = self.app.as_ref().unwrap().config.lock().unwrap();
+		let tts_config = config.tts_config.clone();
+		let sounds: Vec<Sound> = config.sounds
+			.iter()
+			.filter(|sound| sound.tts.is_some() && !Path::new(&sound.path).exists())
+			.cloned()
+			.collect();
+		drop(config);
+		if sounds.is_empty() {
+			return;
+		}
+
+		std::thread::spawn(move || {
+			for sound in sounds {
+				if let Err(err) = crate::tts::ensure_rendered(&tts_config, &sound) {
+					log::error!("Could not render the speech of {}: {}", sound.name, err);
+				}
+			}
+		});
+	}
+
+	fn cover_art(path: &Path) -> Option<SoundImage> {
+		let bytes = crate::images::extract_cover_art(path)?;
+		let id = crate::images::cache_directory().and_then(|dir| crate::images::store_image(&dir, &bytes));
+		match id {
+			Ok(id) => Some(SoundImage::Cached(id)),
+			Err(err) => {
+				log::warn!("Could not read the cover art of {}: {}", path.display(), err);
+				None
+			},
+		}
+	}
+
+	// Groups and schedules cannot refer to a sound that no longer exists, whether it was deleted or its file went away
+	fn remove_sound_references(config: &mut Config, sound_id: &str, removed: &mut RemovedReferences) {
+		removed.groups |= config.remove_sound_references(sound_id);
+		removed.schedules |= config.remove_sound_schedules(sound_id);
+	}
+
+	fn broadcast_removed_references(&self, config: &Config, removed: &RemovedReferences) -> Result<()> {
+		if removed.groups {
+			self.broadcast_sound_groups(config)?;
+		}
+		if removed.schedules {
+			self.broadcast(None, Self::schedules_message(config), Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow]))?;
+		}
+
+		Ok(())
+	}
+
+	// Deletes the files and forgets the plays of the sounds that are gone for good. Sounds that undo can bring back are kept
+	fn forget_deleted_sounds(&self, config: &Config) {
+		self.remove_orphaned_files(config);
+
+		let history = self.history.lock().unwrap();
+		let kept: HashSet<&str> = config.sounds
+			.iter()
+			.chain(history.sounds())
+			.map(|sound| sound.id.as_str())
+			.collect();
+		self.app.as_ref().unwrap().stats.lock().unwrap().retain_sounds(|id| kept.contains(id));
+	}
+
+	// Deletes the files in the sounds directory that no sound uses anymore, including the sounds that undo can bring back
+	fn remove_orphaned_files(&self, config: &Config) {
+		let history = self.history.lock().unwrap();
+		let remove = || -> Result<Vec<PathBuf>> {
+			let app_dir = crate::App::get_working_directory()?;
+			let referenced: Vec<PathBuf> = config.sounds
+				.iter()
+				.chain(history.sounds())
+				.flat_map(|sound| {
+					let image = match &sound.image {
+						Some(SoundImage::File(path)) => Some(app_dir.join(path)),
+						_ => None,
+					};
+					std::iter::once(app_dir.join(&sound.path)).chain(image)
+				})
+				.collect();
+			Ok(library::remove_orphaned_files(&Self::sounds_directory()?, &referenced)?)
+		};
+
+		match remove() {
+			Ok(removed) => {
+				for path in removed {
+					log::info!("Removed unused sound file {}", path.display());
+				}
+			},
+			Err(err) => log::error!("Could not remove unused sound files: {}", err),
+		}
+	}
+
+	fn render_speech(tts_config: &TtsConfig, speech: &Speech) -> Result<String> {
+		let backend = crate::tts::backend(tts_config);
+		let path = crate::tts::render_cached(backend.as_ref(), speech, &crate::tts::cache_directory()?)?;
+		let mut path = path.to_string_lossy().into_owned();
+		Self::process_sound_path(&mut path, None)?;
+		Ok(path)
+	}
+
+	fn add_tts_sound(&self, data: &serde_json::Value) -> Result<()> {
+		let speech: Speech = serde_json::from_value(data["tts"].clone())?;
+		let name: Option<String> = serde_json::from_value(data["name"].clone())?;
+		let tts_config = self.app.as_ref().unwrap().config.lock().unwrap().tts_config.clone();
+		let path = Self::render_speech(&tts_config, &speech)?;
+
+		let mut config = self.app.as_ref().unwrap().config.lock().unwrap();
+		if config.sounds.iter().any(|sound| sound.path == path) {
+			return Err(anyhow::Error::msg("A sound with the same text and voice already exists"));
+		}
+		let name = name.unwrap_or_else(|| speech.text.chars().take(32).collect());
+		let sound = Sound {
+			tts: Some(speech),
+			..Sound::new(name, path)
+		};
+		let msg = json!({
+			"message": "addedSounds",
+			"sounds": [&sound],
+		}).to_string().into();
+		let before = Snapshot::library(&config);
+		config.sounds.push(sound);
+		config.save();
+		self.record_history("addTtsSound", before)?;
+
+		self.broadcast(None, msg, Some(vec![
+			ClientType::SettingsWindow,
+			ClientType::SoundboardWindow,
+			ClientType::BrowserSettingsWindow,
+			ClientType::BrowserSoundboardWindow,
+		]))?;
+
+		Ok(())
+	}
+
+	fn add_sound_from_url(&self, client: &Client, data: &serde_json::Value) -> Result<()> {
+		let url: String = serde_json::from_value(data["url"].clone())?;
+		let request_id: Option<u32> = serde_json::from_value(data["requestId"].clone())?;
+		let sounds_dir = Self::sounds_directory()?;
+		let app = self.app.clone().unwrap();
+		let tx = client.tx.clone();
+
+		// Downloads can be slow, the server must not stay locked in the meantime
+		std::thread::spawn(move || {
+			let send = |msg: serde_json::Value| {
+				let _ = tx.unbounded_send(msg.to_string().into());
+			};
+
+			let mut last_percent = None;
+			let result = crate::downloader::download_sound(&url, &sounds_dir, crate::downloader::max_download_size(), |downloaded, total| {
+				let percent = total.map(|total| downloaded * 100 / total.max(1));
+				if percent.is_some() && percent == last_percent {
+					return;
+				}
+				last_percent = percent;
+				send(json!({
+					"message": "downloadProgress",
+					"requestId": request_id,
+					"url": &url,
+					"downloaded": downloaded,
+					"total": total,
+				}));
+			});
+
+			let result = result.and_then(|path| {
+				app.websocket_server
+					.lock().unwrap()
+					.do_add_sounds(vec![path.to_string_lossy().into_owned()])
+			});
+			match result {
+				Ok(()) => send(json!({
+					"message": "downloadFinished",
+					"requestId": request_id,
+					"url": &url,
+				})),
+				Err(err) => {
+					log::error!("Could not add sound from {}: {}", url, err);
+					send(json!({
+						"message": "downloadFailed",
+						"requestId": request_id,
+						"url": &url,
+						"error": err.to_string(),
+					}));
+				},
+			}
+		});
+
+		Ok(())
+	}
+
+	fn broadcast_recording_state(&self, recording: bool) -> Result<()> {
+		let msg = json!({
+			"message": "recording",
+			"recording": recording,
+		})
+		.to_string()
+		.into();
+		self.broadcast(None, msg, Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow]))
+	}
+
+	fn start_recording(&self, data: &serde_json::Value) -> Result<()> {
+		let device: Option<DeviceId> = serde_json::from_value(data["device"].clone())?;
+		let mut recording = self.recording.lock().unwrap();
+		if recording.is_some() {
+			return Err(anyhow::Error::msg("A recording is already in progress"));
+		}
+
+		let app = self.app.clone().unwrap();
+		let max_duration = Duration::from_secs(app.config.lock().unwrap().recording_config.max_duration_secs);
+		// Two recordings can be made within the same second
+		let stem = format!("recording-{}", chrono::Local::now().format("%Y%m%d-%H%M%S"));
+		let path = crate::utils::unique_path(&Self::sounds_directory()?, &stem, "wav");
+
+		*recording = Some(crate::recorder::Recording::start(device.as_ref(), &path, max_duration, move |result| {
+			// Called from the recording thread, the server is not locked at this point
+			app.websocket_server.lock().unwrap().recording_finished(result);
+		})?);
+		drop(recording);
+		self.broadcast_recording_state(true)?;
+
+		Ok(())
+	}
+
+	fn set_replay(&self, data: &serde_json::Value) -> Result<()> {
+		let app = self.app.as_ref().unwrap();
+		let mut config = app.config.lock().unwrap();
+		let replay: ReplayConfig = serde_json::from_value(data["replay"].clone())?;
+		if replay.duration_secs == 0 || replay.duration_secs > crate::replay::max_duration_secs() {
+			anyhow::bail!("The replay duration must be between 1 and {} seconds", crate::replay::max_duration_secs());
+		}
+
+		// The previous settings are kept, and restarted, when the new ones cannot be started
+		let res = app.replay.lock().unwrap().start(&replay);
+		if res.is_ok() {
+			self.update_replay_shortcut(&replay);
+			config.replay = replay;
+			config.save();
+		} else if let Err(err) = app.replay.lock().unwrap().start(&config.replay) {
+			log::error!("Could not restart instant replay: {}", err);
+		}
+
+		let msg = json!({
+			"message": "configReplay",
+			"config": &config.replay,
+		})
+		.to_string()
+		.into();
+		self.broadcast(None, msg, Some(vec![ClientType::SettingsWindow, ClientType::BrowserSettingsWindow]))?;
+
+		res
+	}
+
+	fn stop_recording(&self) -> Result<()> {
+		if let Some(recording) = self.recording.lock().unwrap().as_ref() {
+			recording.stop();
+		}
+
+		Ok(())
+	}
+
 	fn add_sounds_dialog(&self, client: &Client) -> Result<()> {
 		if let ClientType::SettingsWindow = client.client_type {
 			self.app